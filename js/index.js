@@ -1 +1,3 @@
-import("../pkg/index.js").catch(console.error);
+import("../pkg/index.js")
+  .then((game) => game.start())
+  .catch(console.error);
@@ -7,8 +7,8 @@ use wasm_bindgen::{
 };
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{
-    CanvasRenderingContext2d, Document, Element, HtmlCanvasElement, HtmlElement, HtmlImageElement,
-    Response, Window,
+    CanvasRenderingContext2d, Document, Element, EventTarget, FontFace, Gamepad, HtmlCanvasElement,
+    HtmlElement, HtmlImageElement, Response, Storage, UrlSearchParams, Window,
 };
 
 // Straight taken from https://rustwasm.github.io/book/game-of-life/debugging.html
@@ -56,6 +56,10 @@ pub fn context() -> Result<CanvasRenderingContext2d> {
         })
 }
 
+pub fn device_pixel_ratio() -> Result<f64> {
+    Ok(window()?.device_pixel_ratio())
+}
+
 pub fn spawn_local<F>(future: F)
 where
     F: Future<Output = ()> + 'static,
@@ -100,6 +104,24 @@ pub async fn fetch_array_buffer(resource: &str) -> Result<ArrayBuffer> {
         .map_err(|err| anyhow!("Error converting raw JSValue to ArrayBuffer {:#?}", err))
 }
 
+// Downloads a font and adds it to the document, so canvas text drawn in its
+// family uses it from the first frame
+pub async fn load_font(family: &str, source: &str) -> Result<()> {
+    let font = FontFace::new_with_str(family, &format!("url({})", source))
+        .map_err(|err| anyhow!("Could not create font {} {:#?}", family, err))?;
+    JsFuture::from(
+        font.load()
+            .map_err(|err| anyhow!("Could not start loading font {} {:#?}", family, err))?,
+    )
+    .await
+    .map_err(|err| anyhow!("Could not load font {} {:#?}", family, err))?;
+
+    document()?
+        .fonts()
+        .add(&font)
+        .map_err(|err| anyhow!("Could not add font {} {:#?}", family, err))
+}
+
 pub fn new_image() -> Result<HtmlImageElement> {
     HtmlImageElement::new().map_err(|err| anyhow!("Could not create HtmlImageElement: {:#?}", err))
 }
@@ -115,6 +137,46 @@ pub fn request_animation_frame(callback: &LoopClosure) -> Result<i32> {
         .map_err(|err| anyhow!("Cannot request animation frame {:#?}", err))
 }
 
+pub fn cancel_animation_frame(handle: i32) -> Result<()> {
+    window()?
+        .cancel_animation_frame(handle)
+        .map_err(|err| anyhow!("Cannot cancel animation frame {:#?}", err))
+}
+
+// A callback added to one element. Dropping it removes that callback from that
+// element, leaving any other listeners, like another game's, alone.
+pub struct EventListener<T: ?Sized> {
+    target: EventTarget,
+    event: &'static str,
+    closure: Closure<T>,
+}
+
+impl<T: ?Sized> Drop for EventListener<T> {
+    fn drop(&mut self) {
+        if let Err(err) = self
+            .target
+            .remove_event_listener_with_callback(self.event, self.closure.as_ref().unchecked_ref())
+        {
+            error!("Could not remove {} listener {:#?}", self.event, err);
+        }
+    }
+}
+
+pub fn add_event_listener<T: ?Sized>(
+    target: &EventTarget,
+    event: &'static str,
+    closure: Closure<T>,
+) -> Result<EventListener<T>> {
+    target
+        .add_event_listener_with_callback(event, closure.as_ref().unchecked_ref())
+        .map_err(|err| anyhow!("Could not listen for {} {:#?}", event, err))?;
+    Ok(EventListener {
+        target: target.clone(),
+        event,
+        closure,
+    })
+}
+
 pub fn closure_once<F, A, R>(fn_once: F) -> Closure<F::FnMut>
 where
     F: 'static + WasmClosureFnOnce<A, R>,
@@ -133,6 +195,48 @@ pub fn now() -> Result<f64> {
         .now())
 }
 
+pub fn query_param(name: &str) -> Result<Option<String>> {
+    let search = window()?
+        .location()
+        .search()
+        .map_err(|err| anyhow!("Could not read the page query string {:#?}", err))?;
+
+    UrlSearchParams::new_with_str(&search)
+        .map(|params| params.get(name))
+        .map_err(|err| anyhow!("Could not parse query string {:#?}", err))
+}
+
+fn local_storage() -> Result<Storage> {
+    window()?
+        .local_storage()
+        .map_err(|err| anyhow!("Could not access local storage {:#?}", err))?
+        .ok_or_else(|| anyhow!("No local storage found"))
+}
+
+// The connected gamepads. The browser leaves holes where pads disconnected,
+// which are skipped.
+pub fn gamepads() -> Result<Vec<Gamepad>> {
+    Ok(window()?
+        .navigator()
+        .get_gamepads()
+        .map_err(|err| anyhow!("Could not get gamepads {:#?}", err))?
+        .iter()
+        .filter_map(|gamepad| gamepad.dyn_into::<Gamepad>().ok())
+        .collect())
+}
+
+pub fn local_storage_get(key: &str) -> Result<Option<String>> {
+    local_storage()?
+        .get_item(key)
+        .map_err(|err| anyhow!("Could not read {} from local storage {:#?}", key, err))
+}
+
+pub fn local_storage_set(key: &str, value: &str) -> Result<()> {
+    local_storage()?
+        .set_item(key, value)
+        .map_err(|err| anyhow!("Could not save {} to local storage {:#?}", key, err))
+}
+
 pub fn draw_ui(html: &str) -> Result<()> {
     find_ui()?
         .insert_adjacent_html("afterbegin", html)
@@ -156,6 +260,16 @@ pub fn hide_ui() -> Result<()> {
     }
 }
 
+// Scales and moves the UI over the canvas so it lines up with what is drawn
+pub fn transform_ui(scale: f64, x: f64, y: f64) -> Result<()> {
+    find_ui()?
+        .set_attribute(
+            "style",
+            &format!("transform: translate({}px, {}px) scale({})", x, y, scale),
+        )
+        .map_err(|err| anyhow!("Could not transform the UI {:#?}", err))
+}
+
 fn find_ui() -> Result<Element> {
     document().and_then(|doc| {
         doc.get_element_by_id("ui")
@@ -1,19 +1,63 @@
-use crate::browser::{self, LoopClosure};
+use crate::browser::{self, EventListener, LoopClosure};
 use crate::sound;
-use anyhow::{anyhow, Result};
+pub use actions::{ActionMap, ActionState};
+use animation::FrameTag;
+pub use animation::{Clip, ClipDefinition};
+use anyhow::{anyhow, Context, Result};
+pub use assets::{
+    AssetLoader, ImageHandle, LoadProgress, ParallaxHandle, SoundHandle, SpriteSheetHandle,
+};
 use async_trait::async_trait;
+pub use camera::Camera;
+pub use camera_effects::HitStop;
+pub use draw_list::{DrawList, Layer};
 use futures::channel::{
-    mpsc::{unbounded, UnboundedReceiver},
+    mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
     oneshot::channel,
 };
-use serde::Deserialize;
-use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Mutex};
+use gamepad::GamepadInput;
+pub use gamepad::{GAMEPAD_DOWN, GAMEPAD_RIGHT, GAMEPAD_UP};
+use loading::LoadingScene;
+pub use metrics::{FrameSample, Metrics, METRICS_OVERLAY_KEY};
+pub use parallax::Parallax;
+pub use particles::{Emission, EmitterId, ParticleEffect, ParticleLook, ParticleSystem};
+pub use replay::{load_recording, InputRecorder, InputRecording, InputReplay, RunEvent};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    cell::{self, RefCell},
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    sync::Mutex,
+};
+pub use text::{Font, TextAlign, TextBaseline, TextStyle, DEFAULT_FONT_FAMILY};
+use touch::{PointerChange, TouchInput};
+pub use touch::{VirtualButton, SWIPE_DOWN, TAP};
+pub use tween::{Easing, Sequence, Tween};
+use viewport::{Display, Viewport};
+pub use viewport::{LOGICAL_AREA, LOGICAL_HEIGHT, LOGICAL_WIDTH};
 use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
 use web_sys::AudioContext;
-use web_sys::{AudioBuffer, HtmlElement};
+use web_sys::{AudioBuffer, HtmlCanvasElement, HtmlElement};
 use web_sys::{CanvasRenderingContext2d, HtmlImageElement};
 
-#[derive(Deserialize, Clone)]
+mod actions;
+mod animation;
+mod assets;
+mod camera;
+mod camera_effects;
+mod draw_list;
+mod gamepad;
+mod loading;
+mod metrics;
+mod parallax;
+mod particles;
+mod replay;
+mod text;
+mod touch;
+mod tween;
+mod viewport;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct SheetRect {
     pub x: i16,
     pub y: i16,
@@ -21,25 +65,160 @@ pub struct SheetRect {
     pub h: i16,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SheetSize {
+    pub w: i16,
+    pub h: i16,
+}
+
+// A frame in a TexturePacker sheet. Trimmed frames have their transparent
+// edges cut off, and sprite_source_size says where what's left sits inside
+// the untrimmed source_size.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Cell {
     pub frame: SheetRect,
+    #[serde(default)]
+    pub rotated: bool,
+    #[serde(default)]
+    pub trimmed: bool,
     pub sprite_source_size: SheetRect,
+    pub source_size: SheetSize,
+    // Milliseconds, in sheets exported from Aseprite
+    #[serde(default)]
+    pub duration: Option<u32>,
+}
+
+impl Cell {
+    // Where the frame's pixels are in the sheet image. Rotated frames are
+    // stored turned 90 degrees clockwise, so their width and height swap.
+    pub fn sheet_frame(&self) -> Rect {
+        if self.rotated {
+            Rect::new_from_x_y(self.frame.x, self.frame.y, self.frame.h, self.frame.w)
+        } else {
+            Rect::new_from_x_y(self.frame.x, self.frame.y, self.frame.w, self.frame.h)
+        }
+    }
+
+    // Where to draw the trimmed frame so the untrimmed sprite's top left is
+    // at position. Every frame of an animation shares the untrimmed size, so
+    // anchoring to it keeps the sprite from jittering between frames.
+    pub fn destination(&self, position: Point) -> Rect {
+        Rect::new_from_x_y(
+            position.x + self.sprite_source_size.x,
+            position.y + self.sprite_source_size.y,
+            self.frame.w,
+            self.frame.h,
+        )
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SheetMeta {
+    pub app: Option<String>,
+    pub image: Option<String>,
+    pub format: Option<String>,
+    pub size: Option<SheetSize>,
+    #[serde(default, rename = "frameTags")]
+    pub frame_tags: Vec<FrameTag>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Default)]
+#[serde(from = "SheetFile")]
 pub struct Sheet {
     pub frames: HashMap<String, Cell>,
+    // In the order the file lists them, which Aseprite's tags index into
+    pub frame_names: Vec<String>,
+    pub meta: Option<SheetMeta>,
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Deserialize)]
+struct SheetFile {
+    #[serde(deserialize_with = "deserialize_frames")]
+    frames: Vec<(String, Cell)>,
+    #[serde(default)]
+    meta: Option<SheetMeta>,
+}
+
+impl From<SheetFile> for Sheet {
+    fn from(file: SheetFile) -> Self {
+        Sheet {
+            frame_names: file.frames.iter().map(|(name, _)| name.clone()).collect(),
+            frames: file.frames.into_iter().collect(),
+            meta: file.meta,
+        }
+    }
+}
+
+// TexturePacker exports frames either keyed by name ("JSON (Hash)") or as a
+// list with the name in each entry ("JSON (Array)"). Aseprite does the same.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SheetFrames {
+    Hash(OrderedFrames),
+    Array(Vec<NamedCell>),
+}
+
+// A map of frames that remembers the order they were written in
+struct OrderedFrames(Vec<(String, Cell)>);
+
+impl<'de> Deserialize<'de> for OrderedFrames {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FramesVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for FramesVisitor {
+            type Value = OrderedFrames;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a map of frame names to cells")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> std::result::Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut frames = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    frames.push(entry);
+                }
+                Ok(OrderedFrames(frames))
+            }
+        }
+
+        deserializer.deserialize_map(FramesVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+struct NamedCell {
+    filename: String,
+    #[serde(flatten)]
+    cell: Cell,
+}
+
+fn deserialize_frames<'de, D>(deserializer: D) -> std::result::Result<Vec<(String, Cell)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match SheetFrames::deserialize(deserializer)? {
+        SheetFrames::Hash(OrderedFrames(frames)) => frames,
+        SheetFrames::Array(frames) => frames
+            .into_iter()
+            .map(|named| (named.filename, named.cell))
+            .collect(),
+    })
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Deserialize)]
 pub struct Point {
     pub x: i16,
     pub y: i16,
 }
 
-#[derive(Default)]
+#[derive(Clone, Default, Debug, PartialEq)]
 pub struct Rect {
     pub position: Point,
     pub width: i16,
@@ -66,6 +245,13 @@ impl Rect {
             && self.bottom() > rect.y()
     }
 
+    pub fn contains(&self, point: Point) -> bool {
+        point.x >= self.x()
+            && point.x < self.right()
+            && point.y >= self.y()
+            && point.y < self.bottom()
+    }
+
     pub fn right(&self) -> i16 {
         self.x() + self.width
     }
@@ -85,14 +271,110 @@ impl Rect {
     pub fn y(&self) -> i16 {
         self.position.y
     }
+
+    pub fn translated(&self, offset: Point) -> Rect {
+        Rect::new_from_x_y(
+            self.x() + offset.x,
+            self.y() + offset.y,
+            self.width,
+            self.height,
+        )
+    }
+}
+
+// Blends between two positions, with alpha going from 0.0 (from) to 1.0 (to).
+pub fn lerp(from: i16, to: i16, alpha: f32) -> i16 {
+    from + ((to - from) as f32 * alpha).round() as i16
+}
+
+// How to draw an image other than straight into its destination. Rotation
+// and scale happen around the anchor, given relative to the destination's
+// top left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    // Radians, clockwise
+    rotation: f32,
+    anchor: Point,
+    scale: f32,
+    alpha: f32,
+    // Frames packed turned a quarter clockwise are turned back before the
+    // rest of the transform applies
+    quarter_turned: bool,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            rotation: 0.0,
+            anchor: Point::default(),
+            scale: 1.0,
+            alpha: 1.0,
+            quarter_turned: false,
+        }
+    }
+}
+
+impl Transform {
+    pub fn new() -> Self {
+        Transform::default()
+    }
+
+    pub fn rotate(mut self, rotation: f32, anchor: Point) -> Self {
+        self.rotation = rotation;
+        self.anchor = anchor;
+        self
+    }
+
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn alpha(mut self, alpha: f32) -> Self {
+        self.alpha = alpha.clamp(0.0, 1.0);
+        self
+    }
+
+    fn quarter_turned(mut self, quarter_turned: bool) -> Self {
+        self.quarter_turned = quarter_turned;
+        self
+    }
 }
 
-pub struct Renderer {
+pub trait Renderer {
+    fn clear(&self, rect: &Rect);
+    fn draw_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect);
+    // Draws a frame stored turned 90 degrees clockwise upright again
+    fn draw_rotated_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect);
+    fn draw_transformed_image(
+        &self,
+        image: &HtmlImageElement,
+        frame: &Rect,
+        destination: &Rect,
+        transform: &Transform,
+    );
+    fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point);
+    fn draw_rect(&self, bounding_box: &Rect);
+    fn fill_rect(&self, rect: &Rect, color: &str);
+    fn draw_text(&self, text: &str, location: &Point) -> Result<()>;
+    // A single line; TextStyle::draw wraps text into lines first
+    fn draw_styled_text(&self, text: &str, location: &Point, style: &TextStyle) -> Result<()>;
+    // The width text would be drawn at in font
+    fn measure_text(&self, text: &str, font: &Font) -> Result<i16>;
+}
+
+pub struct CanvasRenderer {
     context: CanvasRenderingContext2d,
 }
 
-impl Renderer {
-    pub fn clear(&self, rect: &Rect) {
+impl CanvasRenderer {
+    pub fn new(context: CanvasRenderingContext2d) -> Self {
+        CanvasRenderer { context }
+    }
+}
+
+impl Renderer for CanvasRenderer {
+    fn clear(&self, rect: &Rect) {
         self.context.clear_rect(
             rect.x().into(),
             rect.y().into(),
@@ -101,7 +383,7 @@ impl Renderer {
         );
     }
 
-    pub fn draw_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
+    fn draw_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
         self.context
             .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
                 image,
@@ -117,14 +399,93 @@ impl Renderer {
             .expect("Drawing is throwing exceptions! Unrecoverable error.");
     }
 
-    pub fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point) {
+    fn draw_rotated_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
+        // Turn the canvas back a quarter around the destination's bottom
+        // left, so the sideways frame's right edge becomes its top.
+        self.context.save();
+        self.context
+            .translate(destination.x().into(), destination.bottom().into())
+            .and_then(|_| self.context.rotate(-std::f64::consts::FRAC_PI_2))
+            .and_then(|_| {
+                self.context
+                    .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                        image,
+                        frame.x().into(),
+                        frame.y().into(),
+                        frame.width.into(),
+                        frame.height.into(),
+                        0.0,
+                        0.0,
+                        destination.height.into(),
+                        destination.width.into(),
+                    )
+            })
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+        self.context.restore();
+    }
+
+    fn draw_transformed_image(
+        &self,
+        image: &HtmlImageElement,
+        frame: &Rect,
+        destination: &Rect,
+        transform: &Transform,
+    ) {
+        let (width, height) = (f64::from(destination.width), f64::from(destination.height));
+        let anchor_x = f64::from(destination.x()) + f64::from(transform.anchor.x);
+        let anchor_y = f64::from(destination.y()) + f64::from(transform.anchor.y);
+        let scale = f64::from(transform.scale);
+
+        // Everything below is undone by the restore, however drawing goes
+        self.context.save();
+        self.context
+            .set_global_alpha(self.context.global_alpha() * f64::from(transform.alpha));
+        let drawn = self
+            .context
+            .translate(anchor_x, anchor_y)
+            .and_then(|_| self.context.rotate(transform.rotation.into()))
+            .and_then(|_| self.context.scale(scale, scale))
+            .and_then(|_| {
+                self.context.translate(
+                    -f64::from(transform.anchor.x),
+                    -f64::from(transform.anchor.y),
+                )
+            })
+            .and_then(|_| {
+                if transform.quarter_turned {
+                    self.context
+                        .translate(0.0, height)
+                        .and_then(|_| self.context.rotate(-std::f64::consts::FRAC_PI_2))
+                        .map(|_| (height, width))
+                } else {
+                    Ok((width, height))
+                }
+            })
+            .and_then(|(draw_width, draw_height)| {
+                self.context
+                    .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                        image,
+                        frame.x().into(),
+                        frame.y().into(),
+                        frame.width.into(),
+                        frame.height.into(),
+                        0.0,
+                        0.0,
+                        draw_width,
+                        draw_height,
+                    )
+            });
+        self.context.restore();
+        drawn.expect("Drawing is throwing exceptions! Unrecoverable error.");
+    }
+
+    fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point) {
         self.context
             .draw_image_with_html_image_element(image, position.x.into(), position.y.into())
             .expect("Drawing is throwing exceptions! Unrecoverable error.");
     }
 
-    #[allow(dead_code)]
-    pub fn draw_rect(&self, bounding_box: &Rect) {
+    fn draw_rect(&self, bounding_box: &Rect) {
         self.context.set_stroke_style(&JsValue::from_str("#FF0000"));
         self.context.begin_path();
         self.context.rect(
@@ -136,14 +497,262 @@ impl Renderer {
         self.context.stroke();
     }
 
-    #[allow(dead_code)]
-    pub fn draw_text(&self, text: &str, location: &Point) -> Result<()> {
-        self.context.set_font("16pt Ken Future");
+    fn fill_rect(&self, rect: &Rect, color: &str) {
+        self.context.save();
+        self.context.set_fill_style(&JsValue::from_str(color));
+        self.context.fill_rect(
+            rect.x().into(),
+            rect.y().into(),
+            rect.width.into(),
+            rect.height.into(),
+        );
+        self.context.restore();
+    }
+
+    fn draw_text(&self, text: &str, location: &Point) -> Result<()> {
+        self.context.set_font(&Font::default().css());
         self.context
             .fill_text(text, location.x.into(), location.y.into())
             .map_err(|err| anyhow!("Error filling text {:#?}", err))?;
         Ok(())
     }
+
+    fn draw_styled_text(&self, text: &str, location: &Point, style: &TextStyle) -> Result<()> {
+        self.context.save();
+        self.context.set_font(&style.font.css());
+        self.context
+            .set_fill_style(&JsValue::from_str(&style.color));
+        self.context.set_text_align(style.align.css());
+        self.context.set_text_baseline(style.baseline.css());
+        let filled = self
+            .context
+            .fill_text(text, location.x.into(), location.y.into());
+        self.context.restore();
+        filled.map_err(|err| anyhow!("Error filling text {:#?}", err))
+    }
+
+    fn measure_text(&self, text: &str, font: &Font) -> Result<i16> {
+        self.context.save();
+        self.context.set_font(&font.css());
+        let metrics = self.context.measure_text(text);
+        self.context.restore();
+        metrics
+            .map(|metrics| metrics.width().ceil() as i16)
+            .map_err(|err| anyhow!("Error measuring text {:#?}", err))
+    }
+}
+
+// Draws through another renderer with everything shifted by an offset, e.g.
+// to blend a scrolling world between two updates without moving it.
+pub struct OffsetRenderer<'a> {
+    renderer: &'a dyn Renderer,
+    offset: Point,
+}
+
+impl<'a> OffsetRenderer<'a> {
+    pub fn new(renderer: &'a dyn Renderer, offset: Point) -> Self {
+        OffsetRenderer { renderer, offset }
+    }
+
+    fn offset_point(&self, point: &Point) -> Point {
+        Point {
+            x: point.x + self.offset.x,
+            y: point.y + self.offset.y,
+        }
+    }
+}
+
+impl Renderer for OffsetRenderer<'_> {
+    fn clear(&self, rect: &Rect) {
+        self.renderer.clear(&rect.translated(self.offset));
+    }
+
+    fn draw_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
+        self.renderer
+            .draw_image(image, frame, &destination.translated(self.offset));
+    }
+
+    fn draw_rotated_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
+        self.renderer
+            .draw_rotated_image(image, frame, &destination.translated(self.offset));
+    }
+
+    fn draw_transformed_image(
+        &self,
+        image: &HtmlImageElement,
+        frame: &Rect,
+        destination: &Rect,
+        transform: &Transform,
+    ) {
+        self.renderer.draw_transformed_image(
+            image,
+            frame,
+            &destination.translated(self.offset),
+            transform,
+        );
+    }
+
+    fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point) {
+        self.renderer
+            .draw_entire_image(image, &self.offset_point(position));
+    }
+
+    fn draw_rect(&self, bounding_box: &Rect) {
+        self.renderer
+            .draw_rect(&bounding_box.translated(self.offset));
+    }
+
+    fn fill_rect(&self, rect: &Rect, color: &str) {
+        self.renderer
+            .fill_rect(&rect.translated(self.offset), color);
+    }
+
+    fn draw_text(&self, text: &str, location: &Point) -> Result<()> {
+        self.renderer.draw_text(text, &self.offset_point(location))
+    }
+
+    fn draw_styled_text(&self, text: &str, location: &Point, style: &TextStyle) -> Result<()> {
+        self.renderer
+            .draw_styled_text(text, &self.offset_point(location), style)
+    }
+
+    fn measure_text(&self, text: &str, font: &Font) -> Result<i16> {
+        self.renderer.measure_text(text, font)
+    }
+}
+
+// Images are recorded by where they are drawn, not by which element was
+// drawn, because reading anything off an HtmlImageElement needs a browser.
+#[cfg(test)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCommand {
+    Clear(Rect),
+    DrawImage {
+        frame: Rect,
+        destination: Rect,
+    },
+    DrawRotatedImage {
+        frame: Rect,
+        destination: Rect,
+    },
+    DrawTransformedImage {
+        frame: Rect,
+        destination: Rect,
+        transform: Transform,
+    },
+    DrawEntireImage(Point),
+    DrawRect(Rect),
+    FillRect {
+        rect: Rect,
+        color: String,
+    },
+    DrawText {
+        text: String,
+        location: Point,
+    },
+    DrawStyledText {
+        text: String,
+        location: Point,
+        style: TextStyle,
+    },
+}
+
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingRenderer {
+    commands: RefCell<Vec<DrawCommand>>,
+}
+
+#[cfg(test)]
+impl RecordingRenderer {
+    pub fn new() -> Self {
+        RecordingRenderer::default()
+    }
+
+    pub fn commands(&self) -> Vec<DrawCommand> {
+        self.commands.borrow().clone()
+    }
+
+    pub fn take_commands(&self) -> Vec<DrawCommand> {
+        self.commands.replace(vec![])
+    }
+
+    fn record(&self, command: DrawCommand) {
+        self.commands.borrow_mut().push(command);
+    }
+}
+
+#[cfg(test)]
+impl Renderer for RecordingRenderer {
+    fn clear(&self, rect: &Rect) {
+        self.record(DrawCommand::Clear(rect.clone()));
+    }
+
+    fn draw_image(&self, _image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
+        self.record(DrawCommand::DrawImage {
+            frame: frame.clone(),
+            destination: destination.clone(),
+        });
+    }
+
+    fn draw_rotated_image(&self, _image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
+        self.record(DrawCommand::DrawRotatedImage {
+            frame: frame.clone(),
+            destination: destination.clone(),
+        });
+    }
+
+    fn draw_transformed_image(
+        &self,
+        _image: &HtmlImageElement,
+        frame: &Rect,
+        destination: &Rect,
+        transform: &Transform,
+    ) {
+        self.record(DrawCommand::DrawTransformedImage {
+            frame: frame.clone(),
+            destination: destination.clone(),
+            transform: *transform,
+        });
+    }
+
+    fn draw_entire_image(&self, _image: &HtmlImageElement, position: &Point) {
+        self.record(DrawCommand::DrawEntireImage(*position));
+    }
+
+    fn draw_rect(&self, bounding_box: &Rect) {
+        self.record(DrawCommand::DrawRect(bounding_box.clone()));
+    }
+
+    fn fill_rect(&self, rect: &Rect, color: &str) {
+        self.record(DrawCommand::FillRect {
+            rect: rect.clone(),
+            color: color.into(),
+        });
+    }
+
+    fn draw_text(&self, text: &str, location: &Point) -> Result<()> {
+        self.record(DrawCommand::DrawText {
+            text: text.into(),
+            location: *location,
+        });
+        Ok(())
+    }
+
+    fn draw_styled_text(&self, text: &str, location: &Point, style: &TextStyle) -> Result<()> {
+        self.record(DrawCommand::DrawStyledText {
+            text: text.into(),
+            location: *location,
+            style: style.clone(),
+        });
+        Ok(())
+    }
+
+    // Without a canvas to measure with, every character is as wide as the
+    // font's point size
+    fn measure_text(&self, text: &str, font: &Font) -> Result<i16> {
+        Ok(text.chars().count() as i16 * font.size() as i16)
+    }
 }
 
 pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
@@ -179,99 +788,495 @@ pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
 
 #[async_trait(?Send)]
 pub trait Game {
-    async fn initialize(&self) -> Result<Box<dyn Game>>;
+    // Runs while the loop draws the loader's progress, so load assets
+    // through it.
+    async fn initialize(&self, loader: &AssetLoader) -> Result<Box<dyn Game>>;
     fn update(&mut self, keystate: &KeyState);
-    fn draw(&self, renderer: &Renderer);
+    // alpha is how far the loop is between the last update and the next one,
+    // from 0.0 to 1.0, for blending positions between the two. Submit draws
+    // to the list rather than drawing straight away; the loop flushes it in
+    // layer order once the game returns.
+    fn draw<'a>(&'a self, draw_list: &mut DrawList<'a>, alpha: f32);
+    fn on_pause(&mut self) {}
+    fn on_resume(&mut self) {}
+    // Called once a frame so the game can report its own counters
+    fn record_metrics(&self, _metrics: &mut Metrics) {}
+    // Runs started or ended since the last call. Each run's input is
+    // recorded separately, so any one of them can be replayed.
+    fn take_run_events(&mut self) -> Vec<RunEvent> {
+        Vec::new()
+    }
+    // Buttons drawn over the game once the player touches the screen
+    fn virtual_buttons(&self) -> Vec<VirtualButton> {
+        Vec::new()
+    }
 }
 
-// Sixty Frames per second, converted to a frame length in milliseconds
-const FRAME_SIZE: f32 = 1.0 / 60.0 * 1000.0;
+const DEFAULT_TICKS_PER_SECOND: f32 = 60.0;
+// Never try to catch up on more than this many updates in a single frame
+const MAX_UPDATES_PER_FRAME: f32 = 10.0;
 pub struct GameLoop {
+    // The length of one update in milliseconds
+    frame_size: f32,
     last_frame: f64,
     accumulated_delta: f32,
+    // Updates the last frame was too long to run
+    dropped_ticks: u32,
+    paused: bool,
 }
 type SharedLoopClosure = Rc<RefCell<Option<LoopClosure>>>;
 
+enum Scene {
+    Loading(LoadingScene),
+    Running(Box<dyn Game>),
+}
+
+// Everything a player can drive the game with, and the recording of it
+struct LiveInput {
+    receiver: UnboundedReceiver<KeyPress>,
+    pointer_receiver: UnboundedReceiver<PointerChange>,
+    touch: TouchInput,
+    gamepads: GamepadInput,
+    recorder: InputRecorder,
+    _listeners: KeyboardListeners,
+    _pointer_listeners: PointerListeners,
+}
+
+impl LiveInput {
+    fn process(&mut self, keystate: &mut KeyState) {
+        process_input(keystate, &mut self.receiver, &mut self.recorder);
+        process_pointer_input(
+            keystate,
+            &mut self.pointer_receiver,
+            &mut self.touch,
+            &mut self.recorder,
+        );
+        process_gamepad_input(keystate, &mut self.gamepads, &mut self.recorder);
+    }
+
+    fn tick(&mut self, keystate: &mut KeyState) {
+        for change in self.touch.tick() {
+            keystate.apply(&change);
+            self.recorder.record(change);
+        }
+        self.recorder.tick();
+    }
+
+    fn save_recording(&self) {
+        if let Err(err) = replay::save_recording(self.recorder.recording()) {
+            error!("Could not save input recording {:#?}", err);
+        }
+    }
+}
+
+// Whatever was recorded since the last save would otherwise be lost
+impl Drop for LiveInput {
+    fn drop(&mut self) {
+        self.save_recording();
+    }
+}
+
+enum InputSource {
+    Live(Box<LiveInput>),
+    Replay(InputReplay),
+}
+
+impl InputSource {
+    fn process(&mut self, keystate: &mut KeyState) {
+        if let InputSource::Live(live) = self {
+            live.process(keystate);
+        }
+    }
+
+    fn tick(&mut self, keystate: &mut KeyState) {
+        match self {
+            InputSource::Live(live) => live.tick(keystate),
+            InputSource::Replay(replay) => replay.tick(keystate),
+        }
+    }
+
+    fn run_event(&mut self, event: RunEvent, keystate: &KeyState) {
+        if let InputSource::Live(live) = self {
+            match event {
+                RunEvent::Started { seed, controls } => {
+                    live.recorder.start(seed, controls, keystate)
+                }
+                RunEvent::Ended => live.save_recording(),
+            }
+        }
+    }
+
+    // Keys held when the canvas loses focus never get their keyup, so the
+    // live input lets go of everything when the loop is interrupted. A
+    // replay gets the same releases from its recording.
+    fn release_all_keys(&mut self, keystate: &mut KeyState) {
+        if let InputSource::Live(live) = self {
+            release_all_keys(keystate, &mut live.recorder);
+        }
+    }
+
+    // Called when the loop is interrupted, which is also the last chance
+    // before the page goes away
+    fn save_recording(&self) {
+        if let InputSource::Live(live) = self {
+            live.save_recording();
+        }
+    }
+
+    fn set_virtual_buttons(&mut self, buttons: Vec<VirtualButton>) {
+        if let InputSource::Live(live) = self {
+            live.touch.set_buttons(buttons);
+        }
+    }
+
+    fn draw(&self, renderer: &dyn Renderer) {
+        if let InputSource::Live(live) = self {
+            live.touch.draw(renderer);
+        }
+    }
+}
+
+// Dropping the handle leaves the loop running; call stop to tear it down.
+pub struct GameLoopHandle {
+    closure: SharedLoopClosure,
+    animation_frame: Rc<cell::Cell<Option<i32>>>,
+    metrics: Rc<RefCell<Metrics>>,
+}
+
+impl GameLoopHandle {
+    // Cancels the pending frame and drops the loop closure, which takes the
+    // game, its input listeners and everything the game loaded with it.
+    pub fn stop(self) -> Result<()> {
+        if let Some(animation_frame) = self.animation_frame.take() {
+            browser::cancel_animation_frame(animation_frame)?;
+        }
+        self.closure.borrow_mut().take();
+        Ok(())
+    }
+
+    // A copy of the metrics as of the last frame
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.borrow().clone()
+    }
+}
+
 impl GameLoop {
-    pub async fn start(game: impl Game) -> Result<()> {
-        let mut keyevent_receiver = prepare_input()?;
-        let mut game = game.initialize().await?;
+    pub fn new() -> Self {
+        GameLoop::with_tick_rate(DEFAULT_TICKS_PER_SECOND)
+    }
 
-        let mut game_loop = GameLoop {
-            last_frame: browser::now()?,
+    fn ticks_per_second(&self) -> f32 {
+        1000.0 / self.frame_size
+    }
+
+    pub fn with_tick_rate(ticks_per_second: f32) -> Self {
+        GameLoop {
+            frame_size: 1000.0 / ticks_per_second,
+            last_frame: 0.0,
             accumulated_delta: 0.0,
-        };
+            dropped_ticks: 0,
+            paused: false,
+        }
+    }
 
-        let renderer = Renderer {
-            context: browser::context()?,
-        };
+    pub async fn start(self, game: impl Game + 'static) -> Result<GameLoopHandle> {
+        let display = Display::new()?;
+        let (receiver, listeners) = prepare_input(display.canvas())?;
+        let (pointer_receiver, pointer_listeners) =
+            prepare_pointer_input(display.canvas(), display.viewport())?;
+        let input = InputSource::Live(Box::new(LiveInput {
+            receiver,
+            pointer_receiver,
+            touch: TouchInput::new(),
+            gamepads: GamepadInput::new(),
+            recorder: InputRecorder::new(),
+            _listeners: listeners,
+            _pointer_listeners: pointer_listeners,
+        }));
+        self.run(game, input, display).await
+    }
+
+    // Drives the game from a recorded run instead of the keyboard.
+    pub async fn replay(
+        self,
+        game: impl Game + 'static,
+        recording: InputRecording,
+    ) -> Result<GameLoopHandle> {
+        self.run(
+            game,
+            InputSource::Replay(InputReplay::new(recording)),
+            Display::new()?,
+        )
+        .await
+    }
+
+    async fn run(
+        mut self,
+        game: impl Game + 'static,
+        mut input: InputSource,
+        display: Display,
+    ) -> Result<GameLoopHandle> {
+        let mut pause_events = prepare_pause_events(display.canvas())?;
+        let mut scene =
+            Scene::Loading(LoadingScene::start(Box::new(game), self.ticks_per_second()));
+
+        self.last_frame = browser::now()?;
+        let mut game_loop = self;
+
+        let renderer = CanvasRenderer::new(display.context());
 
         let f: SharedLoopClosure = Rc::new(RefCell::new(None));
         let g = f.clone();
+        let animation_frame = Rc::new(cell::Cell::new(None));
+        let next_animation_frame = Rc::clone(&animation_frame);
+
+        let metrics = Rc::new(RefCell::new(Metrics::new()));
+        let loop_metrics = Rc::clone(&metrics);
+        // Debug builds have always shown the frame rate
+        metrics
+            .borrow_mut()
+            .set_overlay_visible(cfg!(debug_assertions));
 
         let mut keystate = KeyState::new();
+        // Checked every frame rather than every update, so the overlay can
+        // be toggled while the game is paused
+        let mut overlay_key_down = false;
 
         *g.borrow_mut() = Some(browser::create_raf_closure(move |perf: f64| {
-            process_input(&mut keystate, &mut keyevent_receiver);
+            input.process(&mut keystate);
+            if let Err(err) = display.begin_frame() {
+                error!("Could not prepare the canvas {:#?}", err);
+            }
 
-            let frame_time = perf - game_loop.last_frame;
-            game_loop.accumulated_delta += frame_time as f32;
-            while game_loop.accumulated_delta > FRAME_SIZE {
-                game.update(&keystate);
-                game_loop.accumulated_delta -= FRAME_SIZE;
+            if let Scene::Loading(loading) = &mut scene {
+                if let Some(mut game) = loading.poll() {
+                    input.set_virtual_buttons(game.virtual_buttons());
+                    for event in game.take_run_events() {
+                        input.run_event(event, &keystate);
+                    }
+                    // The time spent loading is not game time either
+                    game_loop.last_frame = perf;
+                    scene = Scene::Running(game);
+                }
             }
-            game_loop.last_frame = perf;
-            game.draw(&renderer);
 
-            if cfg!(debug_assertions) {
-                unsafe {
-                    draw_frame_rate(&renderer, frame_time);
+            match &mut scene {
+                Scene::Loading(loading) => loading.draw(&renderer),
+                Scene::Running(game) => {
+                    let interrupted =
+                        game_loop.process_pause_events(&mut **game, pause_events.receiver());
+                    if interrupted {
+                        input.release_all_keys(&mut keystate);
+                        input.save_recording();
+                    }
+
+                    let mut metrics = loop_metrics.borrow_mut();
+
+                    if keystate.is_pressed(METRICS_OVERLAY_KEY) && !overlay_key_down {
+                        metrics.toggle_overlay();
+                    }
+                    overlay_key_down = keystate.is_pressed(METRICS_OVERLAY_KEY);
+
+                    let frame_time = (perf - game_loop.last_frame) as f32;
+                    let updates = game_loop.accumulate(frame_time, interrupted);
+                    let update_start = browser::now().unwrap_or(perf);
+                    for _ in 0..updates {
+                        input.tick(&mut keystate);
+                        keystate.tick();
+                        game.update(&keystate);
+                        for event in game.take_run_events() {
+                            input.run_event(event, &keystate);
+                        }
+                    }
+                    game_loop.last_frame = perf;
+
+                    let draw_start = browser::now().unwrap_or(perf);
+                    let mut draw_list = DrawList::new();
+                    game.draw(&mut draw_list, game_loop.alpha());
+                    draw_list.flush(&renderer);
+                    input.draw(&renderer);
+                    let draw_end = browser::now().unwrap_or(perf);
+
+                    metrics.record_frame(FrameSample {
+                        frame_time,
+                        updates,
+                        update_time: (draw_start - update_start) as f32,
+                        draw_time: (draw_end - draw_start) as f32,
+                        dropped_ticks: game_loop.dropped_ticks,
+                    });
+                    game.record_metrics(&mut metrics);
+                    metrics.draw(&renderer);
                 }
             }
+            display.end_frame();
 
-            browser::request_animation_frame(f.borrow().as_ref().unwrap()).unwrap();
+            next_animation_frame.set(Some(
+                browser::request_animation_frame(f.borrow().as_ref().unwrap()).unwrap(),
+            ));
         }));
 
-        browser::request_animation_frame(
+        animation_frame.set(Some(browser::request_animation_frame(
             g.borrow()
                 .as_ref()
                 .ok_or_else(|| anyhow!("GameLoop: Loop is None"))?,
-        )?;
-        Ok(())
+        )?));
+
+        Ok(GameLoopHandle {
+            closure: g,
+            animation_frame,
+            metrics,
+        })
     }
 }
 
-unsafe fn draw_frame_rate(renderer: &Renderer, frame_time: f64) {
-    static mut FRAMES_COUNTED: i32 = 0;
-    static mut TOTAL_FRAME_TIME: f64 = 0.0;
-    static mut FRAME_RATE: i32 = 0;
+impl GameLoop {
+    // Adds the time since the last frame and returns how many updates are
+    // due, leaving the remainder for the next frame.
+    fn accumulate(&mut self, frame_time: f32, interrupted: bool) -> u32 {
+        self.dropped_ticks = 0;
+        if interrupted || self.paused {
+            // The time spent away is not game time, so don't replay it
+            self.accumulated_delta = 0.0;
+            return 0;
+        }
 
-    FRAMES_COUNTED += 1;
-    TOTAL_FRAME_TIME += frame_time;
+        let due = self.accumulated_delta + frame_time;
+        let most = self.frame_size * MAX_UPDATES_PER_FRAME;
+        if due > most {
+            self.dropped_ticks = ((due - most) / self.frame_size) as u32;
+        }
+        self.accumulated_delta = due.min(most);
+        let mut updates = 0;
+        while self.accumulated_delta > self.frame_size {
+            self.accumulated_delta -= self.frame_size;
+            updates += 1;
+        }
+        updates
+    }
 
-    if TOTAL_FRAME_TIME > 1000.0 {
-        FRAME_RATE = FRAMES_COUNTED;
-        TOTAL_FRAME_TIME = 0.0;
-        FRAMES_COUNTED = 0;
+    fn alpha(&self) -> f32 {
+        (self.accumulated_delta / self.frame_size).clamp(0.0, 1.0)
     }
 
-    if let Err(err) = renderer.draw_text(
-        &format!("Frame Rate {}", FRAME_RATE),
-        &Point { x: 400, y: 100 },
-    ) {
-        error!("Could not draw text {:#?}", err);
+    // Returns true if the loop was paused at any point since the last frame,
+    // even if it has already been resumed.
+    fn process_pause_events(
+        &mut self,
+        game: &mut dyn Game,
+        pause_receiver: &mut UnboundedReceiver<PauseEvent>,
+    ) -> bool {
+        let mut interrupted = false;
+        while let Ok(Some(event)) = pause_receiver.try_next() {
+            match event {
+                PauseEvent::Pause if !self.paused => {
+                    self.paused = true;
+                    interrupted = true;
+                    game.on_pause();
+                }
+                PauseEvent::Resume if self.paused => {
+                    self.paused = false;
+                    game.on_resume();
+                }
+                _ => {}
+            }
+        }
+        interrupted
+    }
+}
+
+enum PauseEvent {
+    Pause,
+    Resume,
+}
+
+// Keeps the pause callbacks alive while the loop runs and detaches them when
+// it stops.
+struct PauseEvents {
+    receiver: UnboundedReceiver<PauseEvent>,
+    _listeners: Vec<EventListener<dyn FnMut()>>,
+}
+
+impl PauseEvents {
+    fn receiver(&mut self) -> &mut UnboundedReceiver<PauseEvent> {
+        &mut self.receiver
+    }
+}
+
+fn prepare_pause_events(canvas: &HtmlCanvasElement) -> Result<PauseEvents> {
+    let (pause_sender, pause_receiver) = unbounded();
+    let pause_sender = Rc::new(RefCell::new(pause_sender));
+
+    let send = move |sender: &Rc<RefCell<UnboundedSender<PauseEvent>>>, event| {
+        if let Err(err) = sender.borrow_mut().start_send(event) {
+            error!("Could not send pause message {:#?}", err);
+        }
     };
+
+    let visibility_sender = Rc::clone(&pause_sender);
+    let on_visibility_change = browser::closure_wrap(Box::new(move || {
+        let hidden = browser::document()
+            .map(|document| document.hidden())
+            .unwrap_or(false);
+        send(
+            &visibility_sender,
+            if hidden {
+                PauseEvent::Pause
+            } else {
+                PauseEvent::Resume
+            },
+        );
+    }) as Box<dyn FnMut()>);
+
+    let blur_sender = Rc::clone(&pause_sender);
+    let on_blur = browser::closure_wrap(Box::new(move || {
+        send(&blur_sender, PauseEvent::Pause);
+    }) as Box<dyn FnMut()>);
+
+    let focus_sender = Rc::clone(&pause_sender);
+    let on_focus = browser::closure_wrap(Box::new(move || {
+        send(&focus_sender, PauseEvent::Resume);
+    }) as Box<dyn FnMut()>);
+
+    // Clicking the DOM UI (e.g. the New Game button) takes focus away from
+    // the canvas, so treat it as coming back to the game.
+    let ui_sender = Rc::clone(&pause_sender);
+    let on_ui_click = browser::closure_wrap(Box::new(move || {
+        send(&ui_sender, PauseEvent::Resume);
+    }) as Box<dyn FnMut()>);
+
+    let document = browser::document()?;
+    let ui = browser::find_html_element_by_id("ui")?;
+    Ok(PauseEvents {
+        receiver: pause_receiver,
+        _listeners: vec![
+            browser::add_event_listener(&document, "visibilitychange", on_visibility_change)?,
+            browser::add_event_listener(canvas, "blur", on_blur)?,
+            browser::add_event_listener(canvas, "focus", on_focus)?,
+            browser::add_event_listener(&ui, "click", on_ui_click)?,
+        ],
+    })
 }
 
 #[derive(Debug)]
 pub struct KeyState {
-    pressed_keys: HashMap<String, web_sys::KeyboardEvent>,
+    // How many updates each key has been held down for
+    pressed_keys: HashMap<String, u32>,
+    just_pressed: HashSet<String>,
+    just_released: HashSet<String>,
+    // Changes that arrived since the last update, which only become
+    // just_pressed/just_released when the next update starts.
+    pending_pressed: HashSet<String>,
+    pending_released: HashSet<String>,
 }
 
 impl KeyState {
     fn new() -> Self {
         KeyState {
             pressed_keys: HashMap::new(),
+            just_pressed: HashSet::new(),
+            just_released: HashSet::new(),
+            pending_pressed: HashSet::new(),
+            pending_released: HashSet::new(),
         }
     }
 
@@ -279,34 +1284,125 @@ impl KeyState {
         self.pressed_keys.contains_key(code)
     }
 
-    fn set_pressed(&mut self, code: &str, event: web_sys::KeyboardEvent) {
-        self.pressed_keys.insert(code.into(), event);
+    // True only on the first update after the key went down, even if it
+    // was released again before that update ran.
+    pub fn just_pressed(&self, code: &str) -> bool {
+        self.just_pressed.contains(code)
+    }
+
+    #[allow(dead_code)]
+    pub fn just_released(&self, code: &str) -> bool {
+        self.just_released.contains(code)
+    }
+
+    fn pressed_codes(&self) -> Vec<String> {
+        let mut pressed: Vec<String> = self.pressed_keys.keys().cloned().collect();
+        pressed.sort();
+        pressed
+    }
+
+    // Keys that were down before the changes waiting for the next update
+    fn held_codes(&self) -> Vec<String> {
+        let mut held: Vec<String> = self
+            .pressed_keys
+            .keys()
+            .filter(|code| !self.pending_pressed.contains(*code))
+            .cloned()
+            .collect();
+        held.sort();
+        held
+    }
+
+    // Puts a key down without it counting as just pressed, as if it had
+    // been held since before the first update
+    fn hold(&mut self, code: &str) {
+        self.pressed_keys.entry(code.into()).or_insert(0);
+    }
+
+    // The number of updates, including this one, the key has been held for.
+    #[allow(dead_code)]
+    pub fn held_ticks(&self, code: &str) -> u32 {
+        self.pressed_keys.get(code).copied().unwrap_or(0)
+    }
+
+    fn set_pressed(&mut self, code: &str) {
+        // Holding a key down repeats keydown events, which are not new presses
+        if !self.pressed_keys.contains_key(code) {
+            self.pressed_keys.insert(code.into(), 0);
+            self.pending_pressed.insert(code.into());
+        }
     }
 
     fn set_released(&mut self, code: &str) {
-        self.pressed_keys.remove(code);
+        if self.pressed_keys.remove(code).is_some() {
+            self.pending_released.insert(code.into());
+        }
+    }
+
+    fn apply(&mut self, change: &KeyChange) {
+        match change {
+            KeyChange::Pressed(code) => self.set_pressed(code),
+            KeyChange::Released(code) => self.set_released(code),
+        }
+    }
+
+    // Called once before every update
+    fn tick(&mut self) {
+        self.just_pressed = std::mem::take(&mut self.pending_pressed);
+        self.just_released = std::mem::take(&mut self.pending_released);
+        self.pressed_keys
+            .values_mut()
+            .for_each(|held_ticks| *held_ticks += 1);
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum KeyChange {
+    Pressed(String),
+    Released(String),
+}
+
 enum KeyPress {
     KeyUp(web_sys::KeyboardEvent),
     KeyDown(web_sys::KeyboardEvent),
 }
 
-fn process_input(state: &mut KeyState, keyevent_receiver: &mut UnboundedReceiver<KeyPress>) {
+fn process_input(
+    state: &mut KeyState,
+    keyevent_receiver: &mut UnboundedReceiver<KeyPress>,
+    recorder: &mut InputRecorder,
+) {
     loop {
         match keyevent_receiver.try_next() {
             Ok(None) => break,
             Err(_err) => break,
-            Ok(Some(evt)) => match evt {
-                KeyPress::KeyUp(evt) => state.set_released(&evt.code()),
-                KeyPress::KeyDown(evt) => state.set_pressed(&evt.code(), evt),
-            },
+            Ok(Some(evt)) => {
+                let change = match evt {
+                    KeyPress::KeyUp(evt) => KeyChange::Released(evt.code()),
+                    KeyPress::KeyDown(evt) => KeyChange::Pressed(evt.code()),
+                };
+                state.apply(&change);
+                recorder.record(change);
+            }
         };
     }
 }
 
-fn prepare_input() -> Result<UnboundedReceiver<KeyPress>> {
+fn release_all_keys(state: &mut KeyState, recorder: &mut InputRecorder) {
+    for code in state.pressed_codes() {
+        let change = KeyChange::Released(code);
+        state.apply(&change);
+        recorder.record(change);
+    }
+}
+
+// Keeps the keyboard callbacks alive while the loop runs and detaches them
+// from the canvas when it stops.
+type KeyboardListeners = Vec<EventListener<dyn FnMut(web_sys::KeyboardEvent)>>;
+
+fn prepare_input(
+    canvas: &HtmlCanvasElement,
+) -> Result<(UnboundedReceiver<KeyPress>, KeyboardListeners)> {
     let (keydown_sender, keyevent_receiver) = unbounded();
     let keydown_sender = Rc::new(RefCell::new(keydown_sender));
     let keyup_sender = Rc::clone(&keydown_sender);
@@ -328,17 +1424,125 @@ fn prepare_input() -> Result<UnboundedReceiver<KeyPress>> {
         }
     }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
 
-    browser::canvas()?.set_onkeydown(Some(onkeydown.as_ref().unchecked_ref()));
-    browser::canvas()?.set_onkeyup(Some(onkeyup.as_ref().unchecked_ref()));
-    onkeydown.forget();
-    onkeyup.forget();
+    Ok((
+        keyevent_receiver,
+        vec![
+            browser::add_event_listener(canvas, "keydown", onkeydown)?,
+            browser::add_event_listener(canvas, "keyup", onkeyup)?,
+        ],
+    ))
+}
 
-    Ok(keyevent_receiver)
+fn process_pointer_input(
+    state: &mut KeyState,
+    pointer_receiver: &mut UnboundedReceiver<PointerChange>,
+    touch: &mut TouchInput,
+    recorder: &mut InputRecorder,
+) {
+    while let Ok(Some(change)) = pointer_receiver.try_next() {
+        for change in touch.apply(change) {
+            state.apply(&change);
+            recorder.record(change);
+        }
+    }
+}
+
+fn process_gamepad_input(
+    state: &mut KeyState,
+    gamepads: &mut GamepadInput,
+    recorder: &mut InputRecorder,
+) {
+    let pads = match gamepad::read_gamepads() {
+        Ok(pads) => pads,
+        Err(err) => {
+            error!("Could not read gamepads {:#?}", err);
+            return;
+        }
+    };
+
+    let (connected, disconnected) = gamepads.update_connected(&pads);
+    for index in connected {
+        log!("Gamepad {} connected", index);
+    }
+    for index in disconnected {
+        log!("Gamepad {} disconnected", index);
+    }
+
+    for change in gamepads.poll(&pads) {
+        state.apply(&change);
+        recorder.record(change);
+    }
+}
+
+type PointerListeners = Vec<EventListener<dyn FnMut(web_sys::PointerEvent)>>;
+
+fn pointer_position(evt: &web_sys::PointerEvent, viewport: &Viewport) -> Point {
+    viewport.to_logical(evt.offset_x().into(), evt.offset_y().into())
+}
+
+fn prepare_pointer_input(
+    canvas: &HtmlCanvasElement,
+    viewport: Rc<cell::Cell<Viewport>>,
+) -> Result<(UnboundedReceiver<PointerChange>, PointerListeners)> {
+    let up_viewport = Rc::clone(&viewport);
+    let (down_sender, pointer_receiver) = unbounded();
+    let down_sender = Rc::new(RefCell::new(down_sender));
+    let up_sender = Rc::clone(&down_sender);
+    let cancel_sender = Rc::clone(&down_sender);
+    let capturing_canvas = canvas.clone();
+
+    let onpointerdown = browser::closure_wrap(Box::new(move |evt: web_sys::PointerEvent| {
+        // Keep receiving this pointer's events if a swipe leaves the canvas
+        if let Err(err) = capturing_canvas.set_pointer_capture(evt.pointer_id()) {
+            error!("Could not capture pointer {:#?}", err);
+        }
+        if let Err(err) = down_sender.borrow_mut().start_send(PointerChange::Down {
+            id: evt.pointer_id(),
+            position: pointer_position(&evt, &viewport.get()),
+            pointer_type: evt.pointer_type(),
+        }) {
+            error!("Could not send pointerDown message {:#?}", err);
+        }
+    }) as Box<dyn FnMut(web_sys::PointerEvent)>);
+
+    let onpointerup = browser::closure_wrap(Box::new(move |evt: web_sys::PointerEvent| {
+        if let Err(err) = up_sender.borrow_mut().start_send(PointerChange::Up {
+            id: evt.pointer_id(),
+            position: pointer_position(&evt, &up_viewport.get()),
+        }) {
+            error!("Could not send pointerUp message {:#?}", err);
+        }
+    }) as Box<dyn FnMut(web_sys::PointerEvent)>);
+
+    let onpointercancel = browser::closure_wrap(Box::new(move |evt: web_sys::PointerEvent| {
+        if let Err(err) = cancel_sender
+            .borrow_mut()
+            .start_send(PointerChange::Cancel {
+                id: evt.pointer_id(),
+            })
+        {
+            error!("Could not send pointerCancel message {:#?}", err);
+        }
+    }) as Box<dyn FnMut(web_sys::PointerEvent)>);
+
+    Ok((
+        pointer_receiver,
+        vec![
+            browser::add_event_listener(canvas, "pointerdown", onpointerdown)?,
+            browser::add_event_listener(canvas, "pointerup", onpointerup)?,
+            browser::add_event_listener(canvas, "pointercancel", onpointercancel)?,
+        ],
+    ))
 }
 
 pub fn add_click_handler(elem: HtmlElement) -> UnboundedReceiver<()> {
-    let (mut click_sender, click_receiver) = unbounded();
+    let (click_sender, click_receiver) = unbounded();
+    send_clicks(elem, click_sender);
+    click_receiver
+}
 
+// For when the receiver has to exist before the element does
+pub fn send_clicks(elem: HtmlElement, mut click_sender: UnboundedSender<()>) {
     let on_click = browser::closure_wrap(Box::new(move || {
         if let Err(err) = click_sender.start_send(()) {
             error!("Could not send click message {:#?}", err);
@@ -347,7 +1551,6 @@ pub fn add_click_handler(elem: HtmlElement) -> UnboundedReceiver<()> {
 
     elem.set_onclick(Some(on_click.as_ref().unchecked_ref()));
     on_click.forget();
-    click_receiver
 }
 
 pub struct Image {
@@ -365,7 +1568,7 @@ impl Image {
         }
     }
 
-    pub fn draw(&self, renderer: &Renderer) {
+    pub fn draw(&self, renderer: &dyn Renderer) {
         renderer.draw_entire_image(&self.element, &self.bounding_box.position)
     }
 
@@ -389,25 +1592,84 @@ impl Image {
 pub struct SpriteSheet {
     sheet: Sheet,
     image: HtmlImageElement,
+    clips: HashMap<String, Clip>,
 }
 
 impl SpriteSheet {
     pub fn new(sheet: Sheet, image: HtmlImageElement) -> Self {
-        SpriteSheet { sheet, image }
+        SpriteSheet {
+            sheet,
+            image,
+            clips: HashMap::new(),
+        }
+    }
+
+    // Sheets exported from Aseprite come with a clip for each of their tags,
+    // timed in milliseconds
+    pub fn add_aseprite_clips(&mut self, ticks_per_second: f32) -> Result<()> {
+        let clips = animation::aseprite_clips(&self.sheet, ticks_per_second)
+            .context("Could not read the sheet's animations")?;
+        self.clips.extend(clips);
+        Ok(())
+    }
+
+    pub fn add_clips(&mut self, definitions: &HashMap<String, ClipDefinition>) -> Result<()> {
+        for (name, definition) in definitions {
+            let clip = definition
+                .build(&self.sheet)
+                .map_err(|err| err.context(format!("Could not build the {} clip", name)))?;
+            self.clips.insert(name.clone(), clip);
+        }
+        Ok(())
     }
 
     pub fn cell(&self, name: &str) -> Option<&Cell> {
         self.sheet.frames.get(name)
     }
 
-    pub fn draw(&self, renderer: &Renderer, source: &Rect, destination: &Rect) {
-        renderer.draw_image(&self.image, source, destination);
+    pub fn clip(&self, name: &str) -> Option<&Clip> {
+        self.clips.get(name)
+    }
+
+    pub fn draw(&self, renderer: &dyn Renderer, cell: &Cell, destination: &Rect) {
+        if cell.rotated {
+            renderer.draw_rotated_image(&self.image, &cell.sheet_frame(), destination);
+        } else {
+            renderer.draw_image(&self.image, &cell.sheet_frame(), destination);
+        }
+    }
+
+    pub fn draw_transformed(
+        &self,
+        renderer: &dyn Renderer,
+        cell: &Cell,
+        destination: &Rect,
+        transform: &Transform,
+    ) {
+        renderer.draw_transformed_image(
+            &self.image,
+            &cell.sheet_frame(),
+            destination,
+            &transform.quarter_turned(cell.rotated),
+        );
     }
 }
 
 #[derive(Clone)]
 pub struct Audio {
-    context: AudioContext,
+    context: Rc<AudioContextHandle>,
+}
+
+// Closes the context once the last Audio sharing it is dropped, so a stopped
+// game doesn't keep the audio hardware open.
+struct AudioContextHandle(AudioContext);
+
+impl Drop for AudioContextHandle {
+    fn drop(&mut self) {
+        if let Err(err) = sound::close_audio_context(&self.0) {
+            error!("Could not close audio context {:#?}", err);
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -418,14 +1680,14 @@ pub struct Sound {
 impl Audio {
     pub fn new() -> Result<Self> {
         Ok(Audio {
-            context: sound::create_audio_context()?,
+            context: Rc::new(AudioContextHandle(sound::create_audio_context()?)),
         })
     }
 
     pub async fn load_sound(&self, filename: &str) -> Result<Sound> {
         let array_buffer = browser::fetch_array_buffer(filename).await?;
 
-        let audio_buffer = sound::decode_audio_data(&self.context, &array_buffer).await?;
+        let audio_buffer = sound::decode_audio_data(&self.context.0, &array_buffer).await?;
 
         Ok(Sound {
             buffer: audio_buffer,
@@ -433,11 +1695,11 @@ impl Audio {
     }
 
     pub fn play_sound(&self, sound: &Sound) -> Result<()> {
-        sound::play_sound(&self.context, &sound.buffer, sound::LOOPING::No)
+        sound::play_sound(&self.context.0, &sound.buffer, sound::LOOPING::No)
     }
 
     pub fn play_looping_sound(&self, sound: &Sound) -> Result<()> {
-        sound::play_sound(&self.context, &sound.buffer, sound::LOOPING::Yes)
+        sound::play_sound(&self.context.0, &sound.buffer, sound::LOOPING::Yes)
     }
 }
 
@@ -461,4 +1723,317 @@ mod tests {
 
         assert_eq!(rect2.intersects(&rect1), true);
     }
+
+    #[derive(Default)]
+    struct PausableGame {
+        paused: bool,
+        pauses: u8,
+    }
+
+    #[async_trait(?Send)]
+    impl Game for PausableGame {
+        async fn initialize(&self, _loader: &AssetLoader) -> Result<Box<dyn Game>> {
+            Ok(Box::new(PausableGame::default()))
+        }
+        fn update(&mut self, _keystate: &KeyState) {}
+        fn draw<'a>(&'a self, _draw_list: &mut DrawList<'a>, _alpha: f32) {}
+        fn on_pause(&mut self) {
+            self.paused = true;
+            self.pauses += 1;
+        }
+        fn on_resume(&mut self) {
+            self.paused = false;
+        }
+    }
+
+    #[test]
+    fn pausing_and_resuming_within_one_frame_still_interrupts_the_loop() {
+        let mut game_loop = GameLoop::new();
+        let mut game = PausableGame::default();
+        let (mut sender, mut receiver) = unbounded();
+        sender.start_send(PauseEvent::Pause).unwrap();
+        sender.start_send(PauseEvent::Pause).unwrap();
+        sender.start_send(PauseEvent::Resume).unwrap();
+
+        let interrupted = game_loop.process_pause_events(&mut game, &mut receiver);
+
+        assert!(interrupted);
+        assert!(!game_loop.paused);
+        assert!(!game.paused);
+        assert_eq!(game.pauses, 1);
+        assert!(!game_loop.process_pause_events(&mut game, &mut receiver));
+    }
+
+    #[test]
+    fn a_faster_tick_rate_runs_more_updates_per_frame() {
+        let mut sixty = GameLoop::new();
+        let mut one_twenty = GameLoop::with_tick_rate(120.0);
+
+        assert_eq!(sixty.accumulate(50.0, false), 3);
+        assert_eq!(one_twenty.accumulate(50.0, false), 6);
+    }
+
+    #[test]
+    fn alpha_is_the_fraction_of_an_update_left_over() {
+        let mut game_loop = GameLoop::with_tick_rate(100.0);
+
+        game_loop.accumulate(25.0, false);
+
+        assert!((game_loop.alpha() - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn time_away_is_not_caught_up_on() {
+        let mut game_loop = GameLoop::new();
+
+        assert_eq!(game_loop.accumulate(60_000.0, true), 0);
+        assert_eq!(game_loop.alpha(), 0.0);
+        assert_eq!(game_loop.accumulate(60_000.0, false), 9);
+    }
+
+    #[test]
+    fn updates_past_the_limit_are_counted_as_dropped() {
+        let mut game_loop = GameLoop::with_tick_rate(100.0);
+
+        assert_eq!(game_loop.accumulate(250.0, false), 9);
+        assert_eq!(game_loop.dropped_ticks, 15);
+
+        game_loop.accumulate(10.0, false);
+        assert_eq!(game_loop.dropped_ticks, 0);
+    }
+
+    #[test]
+    fn lerp_blends_between_two_positions() {
+        assert_eq!(lerp(0, 10, 0.0), 0);
+        assert_eq!(lerp(0, 10, 0.25), 3);
+        assert_eq!(lerp(10, 0, 0.5), 5);
+        assert_eq!(lerp(0, 10, 1.0), 10);
+    }
+
+    #[test]
+    fn offset_renderer_shifts_everything_it_draws() {
+        let recording = RecordingRenderer::new();
+        let renderer = OffsetRenderer::new(&recording, Point { x: 3, y: -2 });
+
+        renderer.draw_rect(&Rect::new_from_x_y(10, 10, 5, 5));
+        renderer.draw_text("Hi", &Point { x: 0, y: 0 }).unwrap();
+
+        assert_eq!(
+            recording.commands(),
+            vec![
+                DrawCommand::DrawRect(Rect::new_from_x_y(13, 8, 5, 5)),
+                DrawCommand::DrawText {
+                    text: "Hi".into(),
+                    location: Point { x: 3, y: -2 }
+                },
+            ]
+        );
+    }
+
+    fn press(keystate: &mut KeyState, code: &str) {
+        keystate.apply(&KeyChange::Pressed(code.into()));
+    }
+
+    fn release(keystate: &mut KeyState, code: &str) {
+        keystate.apply(&KeyChange::Released(code.into()));
+    }
+
+    #[test]
+    fn a_press_is_only_just_pressed_for_the_first_update_of_a_frame() {
+        let mut keystate = KeyState::new();
+        press(&mut keystate, "Space");
+
+        keystate.tick();
+        assert!(keystate.just_pressed("Space"));
+        assert_eq!(keystate.held_ticks("Space"), 1);
+
+        keystate.tick();
+        assert!(!keystate.just_pressed("Space"));
+        assert!(keystate.is_pressed("Space"));
+        assert_eq!(keystate.held_ticks("Space"), 2);
+    }
+
+    #[test]
+    fn repeated_keydowns_are_not_new_presses() {
+        let mut keystate = KeyState::new();
+        press(&mut keystate, "Space");
+        keystate.tick();
+
+        press(&mut keystate, "Space");
+        keystate.tick();
+
+        assert!(!keystate.just_pressed("Space"));
+        assert_eq!(keystate.held_ticks("Space"), 2);
+    }
+
+    #[test]
+    fn a_release_is_just_released_for_one_update() {
+        let mut keystate = KeyState::new();
+        press(&mut keystate, "ArrowDown");
+        keystate.tick();
+
+        release(&mut keystate, "ArrowDown");
+        keystate.tick();
+        assert!(keystate.just_released("ArrowDown"));
+        assert!(!keystate.is_pressed("ArrowDown"));
+        assert_eq!(keystate.held_ticks("ArrowDown"), 0);
+
+        keystate.tick();
+        assert!(!keystate.just_released("ArrowDown"));
+    }
+
+    #[test]
+    fn a_tap_between_two_updates_is_still_seen() {
+        let mut keystate = KeyState::new();
+        press(&mut keystate, "Space");
+        release(&mut keystate, "Space");
+
+        keystate.tick();
+
+        assert!(keystate.just_pressed("Space"));
+        assert!(keystate.just_released("Space"));
+        assert!(!keystate.is_pressed("Space"));
+    }
+
+    #[test]
+    fn releasing_all_keys_is_recorded_like_any_other_release() {
+        let mut keystate = KeyState::new();
+        let mut recorder = InputRecorder::new();
+        press(&mut keystate, "Space");
+        press(&mut keystate, "ArrowRight");
+        keystate.tick();
+
+        release_all_keys(&mut keystate, &mut recorder);
+        keystate.tick();
+        recorder.tick();
+
+        assert!(!keystate.is_pressed("Space"));
+        assert!(keystate.just_released("ArrowRight"));
+        let mut replayed = KeyState::new();
+        press(&mut replayed, "Space");
+        press(&mut replayed, "ArrowRight");
+        replayed.tick();
+        InputReplay::new(recorder.recording().clone()).tick(&mut replayed);
+        replayed.tick();
+        assert!(!replayed.is_pressed("Space"));
+        assert!(!replayed.is_pressed("ArrowRight"));
+    }
+
+    #[test]
+    fn recording_renderer_keeps_draw_calls_in_order() {
+        let renderer = RecordingRenderer::new();
+
+        renderer.clear(&Rect::new_from_x_y(0, 0, 600, 600));
+        renderer.draw_rect(&Rect::new_from_x_y(10, 20, 30, 40));
+        renderer
+            .draw_text("Hello", &Point { x: 400, y: 100 })
+            .unwrap();
+
+        assert_eq!(
+            renderer.take_commands(),
+            vec![
+                DrawCommand::Clear(Rect::new_from_x_y(0, 0, 600, 600)),
+                DrawCommand::DrawRect(Rect::new_from_x_y(10, 20, 30, 40)),
+                DrawCommand::DrawText {
+                    text: "Hello".into(),
+                    location: Point { x: 400, y: 100 }
+                },
+            ]
+        );
+        assert!(renderer.commands().is_empty());
+    }
+
+    const ARRAY_SHEET: &str = r#"{
+        "frames": [
+            {
+                "filename": "17.png",
+                "frame": {"x":522,"y":1,"w":128,"h":99},
+                "rotated": true,
+                "trimmed": false,
+                "spriteSourceSize": {"x":0,"y":0,"w":128,"h":99},
+                "sourceSize": {"w":128,"h":99}
+            },
+            {
+                "filename": "Dead (1).png",
+                "frame": {"x":0,"y":0,"w":71,"h":115},
+                "rotated": false,
+                "trimmed": true,
+                "spriteSourceSize": {"x":58,"y":8,"w":71,"h":115},
+                "sourceSize": {"w":160,"h":136}
+            }
+        ],
+        "meta": {
+            "app": "https://www.codeandweb.com/texturepacker",
+            "image": "tiles.png",
+            "size": {"w":1024,"h":512},
+            "scale": "1"
+        }
+    }"#;
+
+    #[test]
+    fn sheets_can_use_the_array_layout_and_meta_block() {
+        let sheet: Sheet = serde_json::from_str(ARRAY_SHEET).unwrap();
+
+        assert_eq!(sheet.frames.len(), 2);
+        assert!(sheet.frames["17.png"].rotated);
+        let meta = sheet.meta.unwrap();
+        assert_eq!(meta.image.as_deref(), Some("tiles.png"));
+        assert_eq!(meta.size, Some(SheetSize { w: 1024, h: 512 }));
+    }
+
+    #[test]
+    fn rotated_frames_are_drawn_upright() {
+        let sheet: Sheet = serde_json::from_str(ARRAY_SHEET).unwrap();
+        let cell = sheet.frames["17.png"].clone();
+        let image: HtmlImageElement = JsValue::UNDEFINED.unchecked_into();
+        let sprite_sheet = SpriteSheet::new(sheet, image);
+        let renderer = RecordingRenderer::new();
+
+        sprite_sheet.draw(&renderer, &cell, &cell.destination(Point { x: 10, y: 20 }));
+
+        assert_eq!(
+            renderer.commands(),
+            vec![DrawCommand::DrawRotatedImage {
+                frame: Rect::new_from_x_y(522, 1, 99, 128),
+                destination: Rect::new_from_x_y(10, 20, 128, 99),
+            }]
+        );
+    }
+
+    #[test]
+    fn transformed_frames_remember_they_were_packed_sideways() {
+        let sheet: Sheet = serde_json::from_str(ARRAY_SHEET).unwrap();
+        let cell = sheet.frames["17.png"].clone();
+        let image: HtmlImageElement = JsValue::UNDEFINED.unchecked_into();
+        let sprite_sheet = SpriteSheet::new(sheet, image);
+        let recording = RecordingRenderer::new();
+        let renderer = OffsetRenderer::new(&recording, Point { x: 5, y: 5 });
+        let destination = cell.destination(Point { x: 10, y: 20 });
+        let transform = Transform::new().alpha(1.5);
+
+        sprite_sheet.draw_transformed(&renderer, &cell, &destination, &transform);
+
+        assert_eq!(
+            recording.commands(),
+            vec![DrawCommand::DrawTransformedImage {
+                frame: Rect::new_from_x_y(522, 1, 99, 128),
+                destination: Rect::new_from_x_y(15, 25, 128, 99),
+                transform: Transform {
+                    alpha: 1.0,
+                    quarter_turned: true,
+                    ..Transform::default()
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn trimmed_frames_are_placed_inside_their_source_size() {
+        let sheet: Sheet = serde_json::from_str(ARRAY_SHEET).unwrap();
+
+        assert_eq!(
+            sheet.frames["Dead (1).png"].destination(Point { x: 100, y: 200 }),
+            Rect::new_from_x_y(158, 208, 71, 115)
+        );
+    }
 }
@@ -0,0 +1,175 @@
+use super::KeyState;
+use crate::browser;
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, hash::Hash};
+use wasm_bindgen::JsValue;
+
+// Binds game defined actions (Jump, Slide...) to the physical key codes that
+// trigger them, so games never ask for a key by name.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ActionMap<A: Eq + Hash> {
+    bindings: HashMap<A, Vec<String>>,
+}
+
+impl<A: Copy + Eq + Hash + Serialize + DeserializeOwned> ActionMap<A> {
+    pub fn new() -> Self {
+        ActionMap {
+            bindings: HashMap::new(),
+        }
+    }
+
+    pub fn bind(&mut self, action: A, code: &str) {
+        let codes = self.bindings.entry(action).or_default();
+        if !codes.iter().any(|bound| bound == code) {
+            codes.push(code.into());
+        }
+    }
+
+    // Replaces every binding for the action.
+    pub fn rebind(&mut self, action: A, codes: &[&str]) {
+        self.bindings.remove(&action);
+        codes.iter().for_each(|code| self.bind(action, code));
+    }
+
+    // Takes other's keys for every action it binds, and keeps this map's
+    // keys for the rest
+    pub fn override_with(&mut self, other: &ActionMap<A>) {
+        for (action, codes) in &other.bindings {
+            self.bindings.insert(*action, codes.clone());
+        }
+    }
+
+    pub fn bindings(&self, action: A) -> &[String] {
+        self.bindings
+            .get(&action)
+            .map(|codes| codes.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn state<'a>(&'a self, keystate: &'a KeyState) -> ActionState<'a, A> {
+        ActionState {
+            map: self,
+            keystate,
+        }
+    }
+
+    pub fn load(storage_key: &str) -> Result<Option<Self>> {
+        match browser::local_storage_get(storage_key)? {
+            Some(json) => {
+                let value = js_sys::JSON::parse(&json)
+                    .map_err(|err| anyhow!("Could not parse saved controls {:#?}", err))?;
+                Ok(Some(value.into_serde::<ActionMap<A>>()?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn save(&self, storage_key: &str) -> Result<()> {
+        let json = js_sys::JSON::stringify(&JsValue::from_serde(self)?)
+            .map_err(|err| anyhow!("Could not stringify controls {:#?}", err))?;
+        browser::local_storage_set(storage_key, &String::from(json))
+    }
+}
+
+pub struct ActionState<'a, A: Eq + Hash> {
+    map: &'a ActionMap<A>,
+    keystate: &'a KeyState,
+}
+
+impl<A: Copy + Eq + Hash + Serialize + DeserializeOwned> ActionState<'_, A> {
+    pub fn is_active(&self, action: A) -> bool {
+        self.map
+            .bindings(action)
+            .iter()
+            .any(|code| self.keystate.is_pressed(code))
+    }
+
+    pub fn just_pressed(&self, action: A) -> bool {
+        self.map
+            .bindings(action)
+            .iter()
+            .any(|code| self.keystate.just_pressed(code))
+    }
+
+    #[allow(dead_code)]
+    pub fn just_released(&self, action: A) -> bool {
+        self.map
+            .bindings(action)
+            .iter()
+            .any(|code| self.keystate.just_released(code))
+    }
+
+    #[allow(dead_code)]
+    pub fn held_ticks(&self, action: A) -> u32 {
+        self.map
+            .bindings(action)
+            .iter()
+            .map(|code| self.keystate.held_ticks(code))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::KeyChange;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    enum Action {
+        Jump,
+        Slide,
+    }
+
+    #[test]
+    fn an_action_is_active_when_any_of_its_keys_are_pressed() {
+        let mut map = ActionMap::new();
+        map.bind(Action::Jump, "Space");
+        map.bind(Action::Jump, "ArrowUp");
+        map.bind(Action::Slide, "ArrowDown");
+        let mut keystate = KeyState::new();
+        keystate.apply(&KeyChange::Pressed("ArrowUp".into()));
+
+        assert!(map.state(&keystate).is_active(Action::Jump));
+        assert!(!map.state(&keystate).is_active(Action::Slide));
+    }
+
+    #[test]
+    fn rebinding_replaces_the_old_keys() {
+        let mut map = ActionMap::new();
+        map.bind(Action::Jump, "Space");
+
+        map.rebind(Action::Jump, &["KeyW", "KeyW"]);
+
+        assert_eq!(map.bindings(Action::Jump), &["KeyW".to_string()]);
+    }
+
+    #[test]
+    fn overriding_only_replaces_the_actions_given() {
+        let mut defaults = ActionMap::new();
+        defaults.bind(Action::Jump, "Space");
+        defaults.bind(Action::Slide, "ArrowDown");
+        let mut saved = ActionMap::new();
+        saved.bind(Action::Jump, "KeyW");
+
+        defaults.override_with(&saved);
+
+        assert_eq!(defaults.bindings(Action::Jump), &["KeyW".to_string()]);
+        assert_eq!(defaults.bindings(Action::Slide), &["ArrowDown".to_string()]);
+    }
+
+    #[test]
+    fn bindings_survive_a_round_trip_through_json() {
+        let mut map = ActionMap::new();
+        map.bind(Action::Jump, "Space");
+        map.bind(Action::Slide, "ArrowDown");
+
+        let json = serde_json::to_string(&map).unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<ActionMap<Action>>(&json).unwrap(),
+            map
+        );
+    }
+}
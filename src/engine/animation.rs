@@ -0,0 +1,345 @@
+use super::{Cell, Point, Rect, Sheet};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LoopMode {
+    Loop,
+    // Holds the last frame once the clip has played through
+    Once,
+    // Plays forwards then backwards without repeating the end frames
+    PingPong,
+}
+
+impl Default for LoopMode {
+    fn default() -> Self {
+        LoopMode::Loop
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClipFrame {
+    pub cell: Cell,
+    pub ticks: u32,
+}
+
+// A named animation, with its cells looked up when it is built so drawing a
+// frame is just an index into the list.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Clip {
+    frames: Vec<ClipFrame>,
+    loop_mode: LoopMode,
+    // The point in the untrimmed sprite that sits at the entity's position
+    anchor: Point,
+    duration: u32,
+}
+
+impl Clip {
+    pub fn new(frames: Vec<ClipFrame>, loop_mode: LoopMode, anchor: Point) -> Self {
+        let duration = frames.iter().map(|frame| frame.ticks).sum();
+        Clip {
+            frames,
+            loop_mode,
+            anchor,
+            duration,
+        }
+    }
+
+    // Ticks to play every frame once
+    #[allow(dead_code)]
+    pub fn duration(&self) -> u32 {
+        self.duration
+    }
+
+    pub fn is_finished(&self, tick: u32) -> bool {
+        self.loop_mode == LoopMode::Once && tick >= self.duration
+    }
+
+    pub fn frame_at(&self, tick: u32) -> Option<&Cell> {
+        if self.duration == 0 {
+            return None;
+        }
+
+        let tick = match self.loop_mode {
+            LoopMode::Loop => tick % self.duration,
+            LoopMode::Once => tick.min(self.duration - 1),
+            LoopMode::PingPong => self.ping_pong_tick(tick),
+        };
+
+        let mut start = 0;
+        self.frames
+            .iter()
+            .find(|frame| {
+                start += frame.ticks;
+                tick < start
+            })
+            .map(|frame| &frame.cell)
+    }
+
+    // Maps the tick onto the forward timeline, walking back from the second
+    // to last frame to the second once the end is reached.
+    fn ping_pong_tick(&self, tick: u32) -> u32 {
+        let (first, last) = match (self.frames.first(), self.frames.last()) {
+            (Some(first), Some(last)) if self.frames.len() > 1 => (first.ticks, last.ticks),
+            _ => return tick % self.duration,
+        };
+        let period = self.duration * 2 - first - last;
+        let tick = tick % period;
+        if tick < self.duration {
+            tick
+        } else {
+            self.duration - last - 1 - (tick - self.duration)
+        }
+    }
+
+    // Where to draw the frame at tick so the anchor lands on position
+    pub fn destination(&self, tick: u32, position: Point) -> Option<Rect> {
+        self.frame_at(tick).map(|cell| {
+            cell.destination(Point {
+                x: position.x - self.anchor.x,
+                y: position.y - self.anchor.y,
+            })
+        })
+    }
+}
+
+// How a clip is written in JSON, e.g.
+// { "frames": ["Slide (1).png", { "name": "Slide (2).png", "ticks": 6 }],
+//   "ticksPerFrame": 3, "loopMode": "once" }
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipDefinition {
+    pub frames: Vec<FrameDefinition>,
+    #[serde(default = "default_ticks_per_frame")]
+    pub ticks_per_frame: u32,
+    #[serde(default)]
+    pub loop_mode: LoopMode,
+    #[serde(default)]
+    pub anchor: Point,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum FrameDefinition {
+    Name(String),
+    Timed { name: String, ticks: u32 },
+}
+
+fn default_ticks_per_frame() -> u32 {
+    1
+}
+
+impl ClipDefinition {
+    pub fn build(&self, sheet: &Sheet) -> Result<Clip> {
+        let frames = self
+            .frames
+            .iter()
+            .map(|frame| {
+                let (name, ticks) = match frame {
+                    FrameDefinition::Name(name) => (name, self.ticks_per_frame),
+                    FrameDefinition::Timed { name, ticks } => (name, *ticks),
+                };
+                let cell = sheet
+                    .frames
+                    .get(name)
+                    .ok_or_else(|| anyhow!("No frame named {} in the sheet", name))?;
+                Ok(ClipFrame {
+                    cell: cell.clone(),
+                    ticks,
+                })
+            })
+            .collect::<Result<Vec<ClipFrame>>>()?;
+        Ok(Clip::new(frames, self.loop_mode, self.anchor))
+    }
+}
+
+// A tag from Aseprite's JSON export, naming a run of frames by index
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct FrameTag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize,
+    #[serde(default)]
+    pub direction: String,
+}
+
+// Builds a clip for every tag in an Aseprite sheet. Aseprite times frames in
+// milliseconds, which are rounded to ticks at the game loop's tick rate.
+pub fn aseprite_clips(sheet: &Sheet, ticks_per_second: f32) -> Result<HashMap<String, Clip>> {
+    let tags = match &sheet.meta {
+        Some(meta) => &meta.frame_tags,
+        None => return Ok(HashMap::new()),
+    };
+
+    tags.iter()
+        .map(|tag| {
+            let names = sheet
+                .frame_names
+                .get(tag.from..=tag.to)
+                .ok_or_else(|| anyhow!("Tag {} runs past the last frame", tag.name))?;
+            let mut frames: Vec<ClipFrame> = names
+                .iter()
+                .map(|name| {
+                    let cell = sheet.frames[name].clone();
+                    let ticks = cell
+                        .duration
+                        .map(|duration| {
+                            (duration as f32 * ticks_per_second / 1000.0).round() as u32
+                        })
+                        .unwrap_or(1)
+                        .max(1);
+                    ClipFrame { cell, ticks }
+                })
+                .collect();
+
+            let loop_mode = match tag.direction.as_str() {
+                "pingpong" => LoopMode::PingPong,
+                "reverse" => {
+                    frames.reverse();
+                    LoopMode::Loop
+                }
+                _ => LoopMode::Loop,
+            };
+            Ok((
+                tag.name.clone(),
+                Clip::new(frames, loop_mode, Point::default()),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASEPRITE_SHEET: &str = r#"{
+        "frames": {
+            "boy 0.aseprite": {
+                "frame": { "x": 0, "y": 0, "w": 10, "h": 10 },
+                "spriteSourceSize": { "x": 0, "y": 0, "w": 10, "h": 10 },
+                "sourceSize": { "w": 10, "h": 10 },
+                "duration": 100
+            },
+            "boy 1.aseprite": {
+                "frame": { "x": 10, "y": 0, "w": 10, "h": 10 },
+                "spriteSourceSize": { "x": 0, "y": 0, "w": 10, "h": 10 },
+                "sourceSize": { "w": 10, "h": 10 },
+                "duration": 50
+            },
+            "boy 2.aseprite": {
+                "frame": { "x": 20, "y": 0, "w": 10, "h": 10 },
+                "spriteSourceSize": { "x": 0, "y": 0, "w": 10, "h": 10 },
+                "sourceSize": { "w": 10, "h": 10 },
+                "duration": 50
+            }
+        },
+        "meta": {
+            "app": "https://www.aseprite.org/",
+            "image": "boy.png",
+            "frameTags": [
+                { "name": "Run", "from": 0, "to": 2, "direction": "forward" },
+                { "name": "Blink", "from": 1, "to": 2, "direction": "reverse" }
+            ]
+        }
+    }"#;
+
+    fn sheet() -> Sheet {
+        serde_json::from_str(ASEPRITE_SHEET).unwrap()
+    }
+
+    fn x_at(clip: &Clip, tick: u32) -> Option<i16> {
+        clip.frame_at(tick).map(|cell| cell.frame.x)
+    }
+
+    #[test]
+    fn frames_last_their_own_number_of_ticks() {
+        let definition: ClipDefinition = serde_json::from_str(
+            r#"{
+                "frames": ["boy 0.aseprite", { "name": "boy 1.aseprite", "ticks": 1 }],
+                "ticksPerFrame": 2
+            }"#,
+        )
+        .unwrap();
+        let clip = definition.build(&sheet()).unwrap();
+
+        assert_eq!(clip.duration(), 3);
+        let xs: Vec<Option<i16>> = (0..4).map(|tick| x_at(&clip, tick)).collect();
+        assert_eq!(xs, vec![Some(0), Some(0), Some(10), Some(0)]);
+        assert!(!clip.is_finished(10));
+    }
+
+    #[test]
+    fn once_holds_the_last_frame_and_ping_pong_turns_around() {
+        let definition = |loop_mode| ClipDefinition {
+            frames: ["boy 0.aseprite", "boy 1.aseprite", "boy 2.aseprite"]
+                .iter()
+                .map(|name| FrameDefinition::Name(name.to_string()))
+                .collect(),
+            ticks_per_frame: 1,
+            loop_mode,
+            anchor: Point::default(),
+        };
+
+        let once = definition(LoopMode::Once).build(&sheet()).unwrap();
+        assert_eq!(x_at(&once, 10), Some(20));
+        assert!(!once.is_finished(2));
+        assert!(once.is_finished(3));
+
+        let ping_pong = definition(LoopMode::PingPong).build(&sheet()).unwrap();
+        let xs: Vec<Option<i16>> = (0..6).map(|tick| x_at(&ping_pong, tick)).collect();
+        assert_eq!(
+            xs,
+            vec![Some(0), Some(10), Some(20), Some(10), Some(0), Some(10)]
+        );
+    }
+
+    #[test]
+    fn missing_frames_are_an_error() {
+        let definition: ClipDefinition =
+            serde_json::from_str(r#"{ "frames": ["Nope (1).png"] }"#).unwrap();
+
+        assert!(definition.build(&sheet()).is_err());
+    }
+
+    #[test]
+    fn aseprite_tags_become_clips_in_frame_order() {
+        let clips = aseprite_clips(&sheet(), 60.0).unwrap();
+
+        let run = &clips["Run"];
+        assert_eq!(run.duration(), 12);
+        assert_eq!(x_at(run, 5), Some(0));
+        assert_eq!(x_at(run, 6), Some(10));
+        assert_eq!(x_at(run, 9), Some(20));
+
+        let blink = &clips["Blink"];
+        assert_eq!(x_at(blink, 0), Some(20));
+        assert_eq!(x_at(blink, 3), Some(10));
+    }
+
+    #[test]
+    fn aseprite_durations_follow_the_tick_rate() {
+        let clips = aseprite_clips(&sheet(), 30.0).unwrap();
+
+        let run = &clips["Run"];
+        assert_eq!(run.duration(), 7);
+        assert_eq!(x_at(run, 2), Some(0));
+        assert_eq!(x_at(run, 3), Some(10));
+        assert_eq!(x_at(run, 5), Some(20));
+    }
+
+    #[test]
+    fn the_anchor_offsets_the_destination() {
+        let definition: ClipDefinition = serde_json::from_str(
+            r#"{ "frames": ["boy 0.aseprite"], "anchor": { "x": 5, "y": 10 } }"#,
+        )
+        .unwrap();
+        let clip = definition.build(&sheet()).unwrap();
+
+        assert_eq!(
+            clip.destination(0, Point { x: 100, y: 100 }),
+            Some(Rect::new_from_x_y(95, 90, 10, 10))
+        );
+    }
+}
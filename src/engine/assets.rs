@@ -0,0 +1,370 @@
+use super::{
+    load_image,
+    parallax::{ParallaxLayer, ParallaxLayerDefinition},
+    Audio, ClipDefinition, Parallax, Sheet, Sound, SpriteSheet,
+};
+use crate::browser;
+use anyhow::{anyhow, Context, Result};
+use futures::future::try_join_all;
+use serde::Deserialize;
+use std::{cell::RefCell, collections::HashMap, future::Future, marker::PhantomData, rc::Rc};
+use web_sys::HtmlImageElement;
+
+// Every file a game needs, by the key the game asks for it with, e.g.
+// { "images": { "stone": "Stone.png" } }
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetManifest {
+    #[serde(default)]
+    pub images: HashMap<String, String>,
+    #[serde(default)]
+    pub sprite_sheets: HashMap<String, SpriteSheetSource>,
+    #[serde(default)]
+    pub sounds: HashMap<String, String>,
+    // By family, which is what text is drawn with
+    #[serde(default)]
+    pub fonts: HashMap<String, String>,
+    // Each a JSON list of background layers, which load their own images
+    #[serde(default)]
+    pub parallax: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct SpriteSheetSource {
+    pub json: String,
+    // Defaults to the image named in the sheet's own meta block
+    #[serde(default)]
+    pub image: Option<String>,
+    // Named animations made from the sheet's frames
+    #[serde(default)]
+    pub clips: Option<String>,
+}
+
+impl AssetManifest {
+    async fn load(path: &str) -> Result<Self> {
+        Ok(browser::fetch_json(path)
+            .await?
+            .into_serde::<AssetManifest>()?)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .images
+            .keys()
+            .chain(self.sprite_sheets.keys())
+            .chain(self.sounds.keys())
+            .chain(self.fonts.keys())
+            .chain(self.parallax.keys())
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoadProgress {
+    total: usize,
+    pending: Vec<String>,
+    failed: Option<String>,
+}
+
+impl LoadProgress {
+    pub(super) fn new(keys: Vec<String>) -> Self {
+        LoadProgress {
+            total: keys.len(),
+            pending: keys,
+            failed: None,
+        }
+    }
+
+    pub(super) fn finish(&mut self, key: &str) {
+        self.pending.retain(|pending| pending != key);
+    }
+
+    fn fail(&mut self, message: String) {
+        self.failed.get_or_insert(message);
+    }
+
+    pub fn loaded(&self) -> usize {
+        self.total - self.pending.len()
+    }
+
+    // From 0.0 to 1.0. Nothing is known to be loaded until the manifest is.
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.loaded() as f32 / self.total as f32
+        }
+    }
+
+    // One of the assets still loading. They all load at once, so this is
+    // just the first one alphabetically.
+    pub fn current(&self) -> Option<&str> {
+        self.pending.first().map(|key| key.as_str())
+    }
+
+    // The first error, if anything failed to load
+    pub fn failed(&self) -> Option<&str> {
+        self.failed.as_deref()
+    }
+}
+
+// Names an asset in the manifest, typed by what kind of asset it is so an
+// image can't be asked for as a sound.
+pub struct Handle<T> {
+    key: &'static str,
+    asset: PhantomData<T>,
+}
+
+impl<T> Handle<T> {
+    pub const fn new(key: &'static str) -> Self {
+        Handle {
+            key,
+            asset: PhantomData,
+        }
+    }
+}
+
+pub type ImageHandle = Handle<HtmlImageElement>;
+pub type SpriteSheetHandle = Handle<SpriteSheet>;
+pub type SoundHandle = Handle<Sound>;
+pub type ParallaxHandle = Handle<Parallax>;
+
+// Handed to Game::initialize by the game loop, which draws its progress until
+// the game is ready.
+pub struct AssetLoader {
+    progress: RefCell<LoadProgress>,
+    // The loop's, so animations timed in milliseconds play at their speed
+    ticks_per_second: f32,
+}
+
+impl AssetLoader {
+    pub fn new(ticks_per_second: f32) -> Self {
+        AssetLoader {
+            progress: RefCell::new(LoadProgress::default()),
+            ticks_per_second,
+        }
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        self.progress.borrow().clone()
+    }
+
+    // Loads everything in the manifest at once. Sounds are decoded by the
+    // audio context that will play them.
+    pub async fn load(&self, manifest_path: &str, audio: &Audio) -> Result<Assets> {
+        *self.progress.borrow_mut() = LoadProgress::default();
+        let manifest = match AssetManifest::load(manifest_path)
+            .await
+            .with_context(|| format!("Could not load the manifest {}", manifest_path))
+        {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                self.progress.borrow_mut().fail(format!("{:#}", err));
+                return Err(err);
+            }
+        };
+        *self.progress.borrow_mut() = LoadProgress::new(manifest.keys());
+
+        let images = try_join_all(
+            manifest
+                .images
+                .iter()
+                .map(|(key, path)| self.track(key, load_image(path))),
+        );
+
+        let ticks_per_second = self.ticks_per_second;
+        let sprite_sheets = try_join_all(manifest.sprite_sheets.iter().map(|(key, source)| {
+            self.track(key, async move {
+                let sheet = browser::fetch_json(&source.json)
+                    .await?
+                    .into_serde::<Sheet>()?;
+                let image = source
+                    .image
+                    .as_deref()
+                    .or_else(|| sheet.meta.as_ref()?.image.as_deref())
+                    .ok_or_else(|| anyhow!("{} does not name its image", source.json))?;
+                let image = load_image(image).await?;
+                let mut sprite_sheet = SpriteSheet::new(sheet, image);
+                sprite_sheet.add_aseprite_clips(ticks_per_second)?;
+                if let Some(clips) = &source.clips {
+                    let definitions = browser::fetch_json(clips)
+                        .await?
+                        .into_serde::<HashMap<String, ClipDefinition>>()?;
+                    sprite_sheet.add_clips(&definitions)?;
+                }
+                Ok(Rc::new(sprite_sheet))
+            })
+        }));
+
+        let sounds = try_join_all(
+            manifest
+                .sounds
+                .iter()
+                .map(|(key, path)| self.track(key, audio.load_sound(path))),
+        );
+
+        let fonts = try_join_all(
+            manifest
+                .fonts
+                .iter()
+                .map(|(family, path)| self.track(family, browser::load_font(family, path))),
+        );
+
+        let parallax = try_join_all(manifest.parallax.iter().map(|(key, path)| {
+            self.track(key, async move {
+                let definitions = browser::fetch_json(path)
+                    .await?
+                    .into_serde::<Vec<ParallaxLayerDefinition>>()?;
+                let layers = try_join_all(definitions.iter().map(|definition| async move {
+                    let image = load_image(&definition.image).await?;
+                    let (width, height) = (image.width() as i16, image.height() as i16);
+                    Ok::<ParallaxLayer, anyhow::Error>(ParallaxLayer::new(
+                        image, width, height, definition,
+                    ))
+                }))
+                .await?;
+                Ok(Parallax::new(layers))
+            })
+        }));
+
+        let (images, sprite_sheets, sounds, _fonts, parallax) =
+            futures::try_join!(images, sprite_sheets, sounds, fonts, parallax)?;
+
+        Ok(Assets {
+            images: images.into_iter().collect(),
+            sprite_sheets: sprite_sheets.into_iter().collect(),
+            sounds: sounds.into_iter().collect(),
+            parallax: parallax.into_iter().collect(),
+        })
+    }
+
+    async fn track<T>(
+        &self,
+        key: &str,
+        load: impl Future<Output = Result<T>>,
+    ) -> Result<(String, T)> {
+        match load
+            .await
+            .with_context(|| format!("Could not load {}", key))
+        {
+            Ok(asset) => {
+                self.progress.borrow_mut().finish(key);
+                Ok((key.to_string(), asset))
+            }
+            Err(err) => {
+                self.progress.borrow_mut().fail(format!("{:#}", err));
+                Err(err)
+            }
+        }
+    }
+}
+
+// Everything the manifest listed, loaded and cached by key
+#[derive(Default)]
+pub struct Assets {
+    images: HashMap<String, HtmlImageElement>,
+    sprite_sheets: HashMap<String, Rc<SpriteSheet>>,
+    sounds: HashMap<String, Sound>,
+    parallax: HashMap<String, Parallax>,
+}
+
+impl Assets {
+    pub fn image(&self, handle: &ImageHandle) -> Result<HtmlImageElement> {
+        self.images
+            .get(handle.key)
+            .cloned()
+            .ok_or_else(|| anyhow!("No image named {} in the manifest", handle.key))
+    }
+
+    pub fn sprite_sheet(&self, handle: &SpriteSheetHandle) -> Result<Rc<SpriteSheet>> {
+        self.sprite_sheets
+            .get(handle.key)
+            .cloned()
+            .ok_or_else(|| anyhow!("No sprite sheet named {} in the manifest", handle.key))
+    }
+
+    pub fn sound(&self, handle: &SoundHandle) -> Result<Sound> {
+        self.sounds
+            .get(handle.key)
+            .cloned()
+            .ok_or_else(|| anyhow!("No sound named {} in the manifest", handle.key))
+    }
+
+    // A copy, since each user scrolls it separately
+    pub fn parallax(&self, handle: &ParallaxHandle) -> Result<Parallax> {
+        self.parallax
+            .get(handle.key)
+            .cloned()
+            .ok_or_else(|| anyhow!("No parallax named {} in the manifest", handle.key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_kind_of_asset_from_the_manifest() {
+        let manifest: AssetManifest = serde_json::from_str(
+            r#"{
+                "images": { "stone": "Stone.png" },
+                "spriteSheets": { "tiles": { "json": "tiles.json", "image": "tiles.png" } },
+                "sounds": { "jump": "SFX_Jump_23.mp3" },
+                "fonts": { "Ken Future": "kenney_future_narrow-webfont.woff2" },
+                "parallax": { "walk": "parallax.json" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.images["stone"], "Stone.png");
+        assert_eq!(
+            manifest.sprite_sheets["tiles"],
+            SpriteSheetSource {
+                json: "tiles.json".into(),
+                image: Some("tiles.png".into()),
+                clips: None,
+            }
+        );
+        assert_eq!(
+            manifest.fonts["Ken Future"],
+            "kenney_future_narrow-webfont.woff2"
+        );
+        assert_eq!(manifest.parallax["walk"], "parallax.json");
+        assert_eq!(
+            manifest.keys(),
+            vec!["Ken Future", "jump", "stone", "tiles", "walk"]
+        );
+    }
+
+    #[test]
+    fn progress_counts_the_assets_that_finished() {
+        let mut progress = LoadProgress::new(vec!["jump".into(), "stone".into()]);
+        assert_eq!(progress.current(), Some("jump"));
+
+        progress.finish("jump");
+
+        assert_eq!(progress.loaded(), 1);
+        assert!((progress.fraction() - 0.5).abs() < f32::EPSILON);
+        assert_eq!(progress.current(), Some("stone"));
+    }
+
+    #[test]
+    fn only_the_first_failure_is_kept() {
+        let mut progress = LoadProgress::new(vec!["jump".into()]);
+
+        progress.fail("Could not load jump".into());
+        progress.fail("Could not load stone".into());
+
+        assert_eq!(progress.failed(), Some("Could not load jump"));
+    }
+
+    #[test]
+    fn asking_for_an_asset_that_was_not_loaded_is_an_error() {
+        let assets = Assets::default();
+
+        assert!(assets.sound(&SoundHandle::new("jump")).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use super::{camera_effects::Shake, lerp, OffsetRenderer, Point, Rect, Renderer};
+
+const DEFAULT_SHAKE_OFFSET: f32 = 12.0;
+const DEFAULT_SHAKE_DECAY: f32 = 0.025;
+
+// The part of the world that is on screen. Entities keep world coordinates
+// and are drawn through the camera's renderer, which moves them into view.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Camera {
+    position: Point,
+    previous_position: Point,
+    width: i16,
+    height: i16,
+    // In screen coordinates. A followed target can move freely inside it
+    // before the camera moves.
+    dead_zone: Rect,
+    // In world coordinates. The view never leaves them.
+    bounds: Option<Rect>,
+    shake: Shake,
+}
+
+impl Camera {
+    pub fn new(width: i16, height: i16) -> Self {
+        Camera {
+            position: Point::default(),
+            previous_position: Point::default(),
+            width,
+            height,
+            dead_zone: Rect::new_from_x_y(width / 2, height / 2, 0, 0),
+            bounds: None,
+            shake: Shake::new(DEFAULT_SHAKE_OFFSET, DEFAULT_SHAKE_DECAY),
+        }
+    }
+
+    pub fn with_dead_zone(mut self, dead_zone: Rect) -> Self {
+        self.dead_zone = dead_zone;
+        self
+    }
+
+    pub fn with_bounds(mut self, bounds: Rect) -> Self {
+        self.bounds = Some(bounds);
+        self.clamp();
+        self.previous_position = self.position;
+        self
+    }
+
+    // Moves just far enough to bring target back inside the dead zone. Call
+    // once per update so drawing can blend from the last position.
+    pub fn follow(&mut self, target: Point) {
+        self.previous_position = self.position;
+
+        let zone = self.dead_zone.translated(self.position);
+        if target.x < zone.x() {
+            self.position.x -= zone.x() - target.x;
+        } else if target.x > zone.right() {
+            self.position.x += target.x - zone.right();
+        }
+        if target.y < zone.y() {
+            self.position.y -= zone.y() - target.y;
+        } else if target.y > zone.bottom() {
+            self.position.y += target.y - zone.bottom();
+        }
+
+        self.clamp();
+    }
+
+    fn clamp(&mut self) {
+        if let Some(bounds) = &self.bounds {
+            self.position.x = self
+                .position
+                .x
+                .min(bounds.right() - self.width)
+                .max(bounds.x());
+            self.position.y = self
+                .position
+                .y
+                .min(bounds.bottom() - self.height)
+                .max(bounds.y());
+        }
+    }
+
+    // Moves the camera without following or clamping, for when the world's
+    // origin is moved out from under it.
+    pub fn shift(&mut self, offset: Point) {
+        self.position.x += offset.x;
+        self.position.y += offset.y;
+        self.previous_position.x += offset.x;
+        self.previous_position.y += offset.y;
+    }
+
+    // Shakes the view, harder the more trauma has built up
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.shake.add_trauma(amount);
+    }
+
+    // Call once per update, even while the game is frozen, so shaking
+    // carries on and dies down.
+    pub fn update_shake(&mut self) {
+        self.shake.update();
+    }
+
+    pub fn set_shake_enabled(&mut self, enabled: bool) {
+        self.shake.set_enabled(enabled);
+    }
+
+    pub fn is_shake_enabled(&self) -> bool {
+        self.shake.is_enabled()
+    }
+
+    pub fn view(&self) -> Rect {
+        Rect::new(self.position, self.width, self.height)
+    }
+
+    // The view partway between the last two updates, like everything else
+    // is drawn, thrown off by any shaking.
+    pub fn view_at(&self, alpha: f32) -> Rect {
+        let shake = self.shake.offset();
+        Rect::new(
+            Point {
+                x: lerp(self.previous_position.x, self.position.x, alpha) + shake.x,
+                y: lerp(self.previous_position.y, self.position.y, alpha) + shake.y,
+            },
+            self.width,
+            self.height,
+        )
+    }
+
+    // Draws world coordinates where they appear on screen
+    pub fn renderer<'a>(&self, renderer: &'a dyn Renderer, alpha: f32) -> OffsetRenderer<'a> {
+        let view = self.view_at(alpha);
+        OffsetRenderer::new(
+            renderer,
+            Point {
+                x: -view.x(),
+                y: -view.y(),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{DrawCommand, RecordingRenderer};
+
+    fn camera() -> Camera {
+        Camera::new(600, 600).with_dead_zone(Rect::new_from_x_y(100, 0, 50, 600))
+    }
+
+    #[test]
+    fn the_target_moves_freely_inside_the_dead_zone() {
+        let mut camera = camera();
+
+        camera.follow(Point { x: 120, y: 300 });
+        assert_eq!(camera.view().position, Point { x: 0, y: 0 });
+
+        camera.follow(Point { x: 170, y: 300 });
+        assert_eq!(camera.view().position, Point { x: 20, y: 0 });
+
+        camera.follow(Point { x: 90, y: 300 });
+        assert_eq!(camera.view().position, Point { x: -10, y: 0 });
+    }
+
+    #[test]
+    fn the_view_stays_inside_the_bounds() {
+        let mut camera = camera().with_bounds(Rect::new_from_x_y(0, 0, 1000, 600));
+
+        camera.follow(Point { x: 0, y: 900 });
+        assert_eq!(camera.view().position, Point { x: 0, y: 0 });
+
+        camera.follow(Point { x: 2000, y: 300 });
+        assert_eq!(camera.view().position, Point { x: 400, y: 0 });
+    }
+
+    #[test]
+    fn drawing_blends_between_the_last_two_positions() {
+        let mut camera = camera();
+        camera.follow(Point { x: 190, y: 300 });
+        let recording = RecordingRenderer::new();
+
+        camera
+            .renderer(&recording, 0.5)
+            .draw_rect(&Rect::new_from_x_y(100, 100, 10, 10));
+
+        assert_eq!(
+            recording.commands(),
+            vec![DrawCommand::DrawRect(Rect::new_from_x_y(80, 100, 10, 10))]
+        );
+        assert_eq!(camera.view_at(0.5).position, Point { x: 20, y: 0 });
+    }
+
+    #[test]
+    fn shaking_throws_the_view_off_until_it_dies_down() {
+        let mut camera = camera();
+        camera.add_trauma(1.0);
+        camera.update_shake();
+
+        assert!(camera.view_at(1.0).position != camera.view().position);
+
+        (0..40).for_each(|_| camera.update_shake());
+        assert_eq!(camera.view_at(1.0).position, camera.view().position);
+
+        camera.set_shake_enabled(false);
+        camera.add_trauma(1.0);
+        camera.update_shake();
+        assert_eq!(camera.view_at(1.0).position, camera.view().position);
+    }
+
+    #[test]
+    fn shifting_moves_both_positions() {
+        let mut camera = camera();
+        camera.follow(Point { x: 190, y: 300 });
+
+        camera.shift(Point { x: -40, y: 0 });
+
+        assert_eq!(camera.view().position, Point { x: 0, y: 0 });
+        assert!(camera
+            .view()
+            .intersects(&Rect::new_from_x_y(590, 0, 20, 20)));
+        assert!(!camera
+            .view()
+            .intersects(&Rect::new_from_x_y(-40, 0, 20, 20)));
+    }
+}
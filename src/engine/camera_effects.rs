@@ -0,0 +1,169 @@
+use super::Point;
+
+// Trauma based screen shake, after Squirrel Eiserloh's "Juicing Your Cameras
+// With Math". Hits add trauma, which drains away a little every update, and
+// the view is thrown about by the square of whatever is left, so small hits
+// barely register and big ones settle quickly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shake {
+    // From 0.0, still, to 1.0, as hard as it goes
+    trauma: f32,
+    // Trauma lost per update
+    decay: f32,
+    // How far the view moves at full trauma, in pixels
+    max_offset: f32,
+    enabled: bool,
+    offset: Point,
+    // Xorshift state, so a shake plays out the same way every time
+    noise: u32,
+}
+
+impl Shake {
+    pub fn new(max_offset: f32, decay: f32) -> Self {
+        Shake {
+            trauma: 0.0,
+            decay,
+            max_offset,
+            enabled: true,
+            offset: Point::default(),
+            noise: 0x9E37_79B9,
+        }
+    }
+
+    pub fn add_trauma(&mut self, amount: f32) {
+        if self.enabled {
+            self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+        }
+    }
+
+    // Turning shake off stops any that's already going
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.trauma = 0.0;
+            self.offset = Point::default();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    #[allow(dead_code)]
+    pub fn trauma(&self) -> f32 {
+        self.trauma
+    }
+
+    // Picks this update's offset, then lets some trauma drain away. Call once
+    // per update.
+    pub fn update(&mut self) {
+        if self.trauma <= 0.0 {
+            self.offset = Point::default();
+            return;
+        }
+
+        let strength = self.max_offset * self.trauma * self.trauma;
+        self.offset = Point {
+            x: (strength * self.next_noise()).round() as i16,
+            y: (strength * self.next_noise()).round() as i16,
+        };
+        self.trauma = (self.trauma - self.decay).max(0.0);
+    }
+
+    // How far to move the view from where it would otherwise be
+    pub fn offset(&self) -> Point {
+        self.offset
+    }
+
+    // Between -1.0 and 1.0
+    fn next_noise(&mut self) -> f32 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+// A short freeze on impact. While it lasts the game skips its simulation
+// ticks, so everything holds still for a beat before carrying on.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HitStop {
+    remaining: u32,
+}
+
+impl HitStop {
+    // Freezes for ticks updates, or longer if an earlier freeze has more left
+    pub fn freeze(&mut self, ticks: u32) {
+        self.remaining = self.remaining.max(ticks);
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.remaining > 0
+    }
+
+    // Call once per update. Returns true while the update should be skipped.
+    pub fn update(&mut self) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+        self.remaining -= 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trauma_shakes_the_view_then_drains_away() {
+        let mut shake = Shake::new(10.0, 0.25);
+        shake.update();
+        assert_eq!(shake.offset(), Point::default());
+
+        shake.add_trauma(0.6);
+        shake.add_trauma(0.6);
+        assert_eq!(shake.trauma(), 1.0);
+
+        let offsets: Vec<Point> = (0..5)
+            .map(|_| {
+                shake.update();
+                shake.offset()
+            })
+            .collect();
+
+        assert!(offsets[0] != Point::default());
+        assert!(offsets
+            .iter()
+            .all(|offset| offset.x.abs() <= 10 && offset.y.abs() <= 10));
+        assert_eq!(offsets[4], Point::default());
+        assert_eq!(shake.trauma(), 0.0);
+    }
+
+    #[test]
+    fn disabled_shake_ignores_trauma() {
+        let mut shake = Shake::new(10.0, 0.1);
+        shake.add_trauma(1.0);
+        shake.update();
+
+        shake.set_enabled(false);
+        assert_eq!(shake.offset(), Point::default());
+        shake.add_trauma(1.0);
+        shake.update();
+
+        assert_eq!(shake.trauma(), 0.0);
+        assert_eq!(shake.offset(), Point::default());
+    }
+
+    #[test]
+    fn hit_stop_skips_the_longest_freeze_asked_for() {
+        let mut hit_stop = HitStop::default();
+        hit_stop.freeze(3);
+        hit_stop.freeze(2);
+
+        let skipped: Vec<bool> = (0..5).map(|_| hit_stop.update()).collect();
+
+        assert_eq!(skipped, vec![true, true, true, false, false]);
+        assert!(!hit_stop.is_frozen());
+    }
+}
@@ -0,0 +1,107 @@
+use super::Renderer;
+
+// Layers are drawn in the order they're declared here, so anything on a
+// later layer is always on top of anything on an earlier one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Layer {
+    Background,
+    World,
+    // In front of the boy and the platforms, for scenery he passes behind.
+    // The game has none yet, but the slot is here so adding some doesn't
+    // mean reordering the layers around it.
+    #[allow(dead_code)]
+    Foreground,
+    Effects,
+    Hud,
+}
+
+type DrawFn<'a> = Box<dyn Fn(&dyn Renderer) + 'a>;
+
+struct Submission<'a> {
+    layer: Layer,
+    z: i16,
+    draw: DrawFn<'a>,
+}
+
+// Everything a game wants drawn this frame. Draws are submitted in whatever
+// order is convenient, tagged with a layer and a z value within it, and
+// sorted once when the list is flushed. Draws with the same layer and z keep
+// the order they were submitted in.
+#[derive(Default)]
+pub struct DrawList<'a> {
+    submissions: Vec<Submission<'a>>,
+}
+
+impl<'a> DrawList<'a> {
+    pub fn new() -> Self {
+        DrawList::default()
+    }
+
+    pub fn submit(&mut self, layer: Layer, z: i16, draw: impl Fn(&dyn Renderer) + 'a) {
+        self.submissions.push(Submission {
+            layer,
+            z,
+            draw: Box::new(draw),
+        });
+    }
+
+    pub fn flush(mut self, renderer: &dyn Renderer) {
+        // sort_by_key is stable, which keeps ties in submission order
+        self.submissions
+            .sort_by_key(|submission| (submission.layer, submission.z));
+        for submission in &self.submissions {
+            (submission.draw)(renderer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{DrawCommand, RecordingRenderer, Rect};
+
+    fn rect(x: i16) -> Rect {
+        Rect::new_from_x_y(x, 0, 1, 1)
+    }
+
+    #[test]
+    fn submissions_are_drawn_by_layer_then_z() {
+        let renderer = RecordingRenderer::new();
+        let mut list = DrawList::new();
+
+        list.submit(Layer::Hud, 0, |renderer| renderer.draw_rect(&rect(4)));
+        list.submit(Layer::World, 1, |renderer| renderer.draw_rect(&rect(3)));
+        list.submit(Layer::World, -1, |renderer| renderer.draw_rect(&rect(2)));
+        list.submit(Layer::Background, 100, |renderer| {
+            renderer.draw_rect(&rect(1))
+        });
+        list.flush(&renderer);
+
+        assert_eq!(
+            renderer.commands(),
+            (1..=4)
+                .map(|x| DrawCommand::DrawRect(rect(x)))
+                .collect::<Vec<DrawCommand>>()
+        );
+    }
+
+    #[test]
+    fn ties_keep_the_order_they_were_submitted_in() {
+        let renderer = RecordingRenderer::new();
+        let mut list = DrawList::new();
+
+        for x in 0..5 {
+            list.submit(Layer::World, 0, move |renderer| {
+                renderer.draw_rect(&rect(x))
+            });
+        }
+        list.flush(&renderer);
+
+        assert_eq!(
+            renderer.commands(),
+            (0..5)
+                .map(|x| DrawCommand::DrawRect(rect(x)))
+                .collect::<Vec<DrawCommand>>()
+        );
+    }
+}
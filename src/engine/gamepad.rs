@@ -0,0 +1,219 @@
+use super::KeyChange;
+use crate::browser;
+use anyhow::Result;
+use std::collections::HashSet;
+use wasm_bindgen::JsCast;
+
+// Button codes for the "standard" gamepad layout, by button index
+// https://w3c.github.io/gamepad/#remapping
+const BUTTON_CODES: [&str; 17] = [
+    "GamepadA",
+    "GamepadB",
+    "GamepadX",
+    "GamepadY",
+    "GamepadLeftBumper",
+    "GamepadRightBumper",
+    "GamepadLeftTrigger",
+    "GamepadRightTrigger",
+    "GamepadSelect",
+    "GamepadStart",
+    "GamepadLeftStick",
+    "GamepadRightStick",
+    GAMEPAD_UP,
+    GAMEPAD_DOWN,
+    GAMEPAD_LEFT,
+    GAMEPAD_RIGHT,
+    "GamepadHome",
+];
+
+// The left stick doubles as the D-pad, so these are what games should bind
+pub const GAMEPAD_UP: &str = "GamepadUp";
+pub const GAMEPAD_DOWN: &str = "GamepadDown";
+pub const GAMEPAD_LEFT: &str = "GamepadLeft";
+pub const GAMEPAD_RIGHT: &str = "GamepadRight";
+
+// How far the stick has to be pushed before it counts as a D-pad press
+const STICK_THRESHOLD: f64 = 0.5;
+
+// A snapshot of one controller, copied out of the browser's Gamepad so the
+// mapping can be exercised without one.
+#[derive(Clone, Debug, Default)]
+pub struct GamepadState {
+    pub index: u32,
+    pub buttons: Vec<bool>,
+    pub axes: Vec<f64>,
+}
+
+impl GamepadState {
+    fn pressed_codes(&self) -> impl Iterator<Item = &'static str> + '_ {
+        let buttons = self
+            .buttons
+            .iter()
+            .zip(BUTTON_CODES.iter())
+            .filter(|(pressed, _)| **pressed)
+            .map(|(_, code)| *code);
+
+        let axis = |index: usize| self.axes.get(index).copied().unwrap_or(0.0);
+        let stick = [
+            (axis(0) <= -STICK_THRESHOLD, GAMEPAD_LEFT),
+            (axis(0) >= STICK_THRESHOLD, GAMEPAD_RIGHT),
+            (axis(1) <= -STICK_THRESHOLD, GAMEPAD_UP),
+            (axis(1) >= STICK_THRESHOLD, GAMEPAD_DOWN),
+        ];
+
+        buttons.chain(
+            stick
+                .into_iter()
+                .filter(|(pushed, _)| *pushed)
+                .map(|(_, code)| code),
+        )
+    }
+}
+
+// Gamepads can't send events for their buttons, only be polled, so this turns
+// each poll into the key changes since the last one.
+#[derive(Default)]
+pub struct GamepadInput {
+    pressed: HashSet<&'static str>,
+    connected: HashSet<u32>,
+}
+
+impl GamepadInput {
+    pub fn new() -> Self {
+        GamepadInput::default()
+    }
+
+    // Every connected pad drives the same codes, so a button stays pressed
+    // while any pad holds it. A pad that disconnects releases everything it
+    // was holding.
+    pub fn poll(&mut self, gamepads: &[GamepadState]) -> Vec<KeyChange> {
+        let pressed: HashSet<&'static str> = gamepads
+            .iter()
+            .flat_map(|gamepad| gamepad.pressed_codes())
+            .collect();
+
+        let mut changes: Vec<KeyChange> = self
+            .pressed
+            .difference(&pressed)
+            .map(|code| KeyChange::Released(code.to_string()))
+            .collect();
+        changes.extend(
+            pressed
+                .difference(&self.pressed)
+                .map(|code| KeyChange::Pressed(code.to_string())),
+        );
+        // Sets don't have an order, but recordings should
+        changes.sort_by_key(|change| match change {
+            KeyChange::Released(code) => (0, code.clone()),
+            KeyChange::Pressed(code) => (1, code.clone()),
+        });
+
+        self.pressed = pressed;
+        changes
+    }
+
+    // Returns the pads that connected and disconnected since the last call.
+    pub fn update_connected(&mut self, gamepads: &[GamepadState]) -> (Vec<u32>, Vec<u32>) {
+        let connected: HashSet<u32> = gamepads.iter().map(|gamepad| gamepad.index).collect();
+        let added = connected.difference(&self.connected).copied().collect();
+        let removed = self.connected.difference(&connected).copied().collect();
+        self.connected = connected;
+        (added, removed)
+    }
+}
+
+pub fn read_gamepads() -> Result<Vec<GamepadState>> {
+    Ok(browser::gamepads()?
+        .iter()
+        .filter(|gamepad| gamepad.connected())
+        .map(|gamepad| GamepadState {
+            index: gamepad.index(),
+            buttons: gamepad
+                .buttons()
+                .iter()
+                .map(|button| {
+                    button
+                        .dyn_into::<web_sys::GamepadButton>()
+                        .map(|button| button.pressed())
+                        .unwrap_or(false)
+                })
+                .collect(),
+            axes: gamepad
+                .axes()
+                .iter()
+                .map(|axis| axis.as_f64().unwrap_or(0.0))
+                .collect(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gamepad(index: u32, pressed: &[usize]) -> GamepadState {
+        let mut buttons = vec![false; BUTTON_CODES.len()];
+        pressed.iter().for_each(|button| buttons[*button] = true);
+        GamepadState {
+            index,
+            buttons,
+            axes: vec![0.0, 0.0],
+        }
+    }
+
+    #[test]
+    fn buttons_and_the_d_pad_map_to_key_changes() {
+        let mut input = GamepadInput::new();
+
+        assert_eq!(
+            input.poll(&[gamepad(0, &[0, 13])]),
+            vec![
+                KeyChange::Pressed("GamepadA".into()),
+                KeyChange::Pressed(GAMEPAD_DOWN.into())
+            ]
+        );
+        assert!(input.poll(&[gamepad(0, &[0, 13])]).is_empty());
+        assert_eq!(
+            input.poll(&[gamepad(0, &[13])]),
+            vec![KeyChange::Released("GamepadA".into())]
+        );
+    }
+
+    #[test]
+    fn the_left_stick_presses_the_d_pad() {
+        let mut input = GamepadInput::new();
+        let mut pushed_right = gamepad(0, &[]);
+        pushed_right.axes = vec![0.9, 0.1];
+
+        assert_eq!(
+            input.poll(&[pushed_right]),
+            vec![KeyChange::Pressed(GAMEPAD_RIGHT.into())]
+        );
+    }
+
+    #[test]
+    fn disconnecting_releases_held_buttons() {
+        let mut input = GamepadInput::new();
+        input.poll(&[gamepad(0, &[0]), gamepad(1, &[0, 9])]);
+
+        assert_eq!(
+            input.poll(&[gamepad(0, &[0])]),
+            vec![KeyChange::Released("GamepadStart".into())]
+        );
+        assert_eq!(
+            input.poll(&[]),
+            vec![KeyChange::Released("GamepadA".into())]
+        );
+    }
+
+    #[test]
+    fn tracks_pads_connecting_and_disconnecting() {
+        let mut input = GamepadInput::new();
+
+        assert_eq!(
+            input.update_connected(&[gamepad(0, &[])]),
+            (vec![0], vec![])
+        );
+        assert_eq!(input.update_connected(&[]), (vec![], vec![0]));
+    }
+}
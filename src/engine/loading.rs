@@ -0,0 +1,163 @@
+use super::{
+    add_click_handler, AssetLoader, Font, Game, LoadProgress, Point, Rect, Renderer, TextAlign,
+    TextBaseline, TextStyle, LOGICAL_AREA,
+};
+use crate::browser;
+use anyhow::Result;
+use futures::channel::mpsc::UnboundedReceiver;
+use std::{cell::RefCell, rc::Rc};
+
+const PROGRESS_BAR: Rect = Rect::new_from_x_y(100, 290, 400, 20);
+const LABEL_LINE_HEIGHT: i16 = 25;
+
+type Initialized = (Box<dyn Game>, Result<Box<dyn Game>>);
+
+// Runs Game::initialize in the background and draws its progress, so the
+// canvas isn't blank while assets download. If initializing fails, the
+// error is shown with a button to try again.
+pub struct LoadingScene {
+    loader: Rc<AssetLoader>,
+    initialized: Rc<RefCell<Option<Initialized>>>,
+    // Kept between attempts so it can be initialized again after a failure
+    game: Option<Box<dyn Game>>,
+    retry_event: Option<UnboundedReceiver<()>>,
+}
+
+impl LoadingScene {
+    pub fn start(game: Box<dyn Game>, ticks_per_second: f32) -> Self {
+        let mut scene = LoadingScene {
+            loader: Rc::new(AssetLoader::new(ticks_per_second)),
+            initialized: Rc::new(RefCell::new(None)),
+            game: Some(game),
+            retry_event: None,
+        };
+        scene.initialize();
+        scene
+    }
+
+    fn initialize(&mut self) {
+        if let Some(game) = self.game.take() {
+            let loader = Rc::clone(&self.loader);
+            let initialized = Rc::clone(&self.initialized);
+            browser::spawn_local(async move {
+                let result = game.initialize(&loader).await;
+                *initialized.borrow_mut() = Some((game, result));
+            });
+        }
+    }
+
+    // Returns the initialized game once it is ready
+    pub fn poll(&mut self) -> Option<Box<dyn Game>> {
+        if let Some(retry_event) = &mut self.retry_event {
+            if matches!(retry_event.try_next(), Ok(Some(()))) {
+                self.retry_event = None;
+                if let Err(err) = browser::hide_ui() {
+                    error!("Could not hide the loading error {:#?}", err);
+                }
+                self.initialize();
+            }
+        }
+
+        let (game, result) = self.initialized.borrow_mut().take()?;
+        match result {
+            Ok(initialized) => Some(initialized),
+            Err(err) => {
+                error!("Could not initialize the game {:#?}", err);
+                self.game = Some(game);
+                self.show_error(&format!("{:#}", err));
+                None
+            }
+        }
+    }
+
+    fn show_error(&mut self, message: &str) {
+        match browser::draw_ui(&format!(
+            "<div id='loading_error'><p>{}</p><button id='retry'>Retry</button></div>",
+            escape_html(message)
+        ))
+        .and_then(|_unit| browser::find_html_element_by_id("retry"))
+        {
+            Ok(retry) => self.retry_event = Some(add_click_handler(retry)),
+            Err(err) => {
+                error!("Could not show the loading error {:#?}", err);
+            }
+        }
+    }
+
+    pub fn draw(&self, renderer: &dyn Renderer) {
+        draw_progress(renderer, &self.loader.progress());
+    }
+}
+
+fn draw_progress(renderer: &dyn Renderer, progress: &LoadProgress) {
+    renderer.clear(&LOGICAL_AREA);
+
+    let label = match (progress.failed(), progress.current()) {
+        (Some(_), _) => "Loading failed".to_string(),
+        (None, Some(current)) => format!("Loading {}", current),
+        (None, None) => "Loading".to_string(),
+    };
+    // Under the bar and no wider than it, so long asset names wrap downwards
+    let location = Point {
+        x: PROGRESS_BAR.x() + PROGRESS_BAR.width / 2,
+        y: PROGRESS_BAR.bottom() + 10,
+    };
+    if let Err(err) = label_style().draw(renderer, &label, &location) {
+        error!("Could not draw loading text {:#?}", err);
+    }
+
+    renderer.draw_rect(&PROGRESS_BAR);
+    let filled = (PROGRESS_BAR.width as f32 * progress.fraction()) as i16;
+    renderer.fill_rect(
+        &Rect::new(PROGRESS_BAR.position, filled, PROGRESS_BAR.height),
+        "#3366CC",
+    );
+}
+
+fn label_style() -> TextStyle {
+    TextStyle::new(Font::default())
+        .align(TextAlign::Center)
+        .baseline(TextBaseline::Top)
+        .wrap(PROGRESS_BAR.width, LABEL_LINE_HEIGHT)
+}
+
+// Error messages can quote whatever the browser threw, markup included
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{DrawCommand, RecordingRenderer};
+
+    #[test]
+    fn the_progress_bar_fills_as_assets_load() {
+        let renderer = RecordingRenderer::new();
+        let mut progress = LoadProgress::new(vec!["rhb".into(), "stone".into()]);
+        progress.finish("rhb");
+
+        draw_progress(&renderer, &progress);
+
+        let commands = renderer.commands();
+        assert!(commands.contains(&DrawCommand::DrawStyledText {
+            text: "Loading stone".into(),
+            location: Point { x: 300, y: 320 },
+            style: label_style(),
+        }));
+        assert!(commands.contains(&DrawCommand::FillRect {
+            rect: Rect::new_from_x_y(100, 290, 200, 20),
+            color: "#3366CC".into(),
+        }));
+    }
+
+    #[test]
+    fn errors_are_escaped_before_they_go_in_the_page() {
+        assert_eq!(
+            escape_html("Could not load <img> & more"),
+            "Could not load &lt;img&gt; &amp; more"
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use super::{Point, Rect, Renderer};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+// How many frames the overlay graphs look back over
+const HISTORY_LENGTH: usize = 100;
+pub const METRICS_OVERLAY_KEY: &str = "Backquote";
+
+const OVERLAY: Rect = Rect::new_from_x_y(380, 90, 210, 200);
+const GRAPH_HEIGHT: i16 = 40;
+// Milliseconds covered by the full height of a graph
+const GRAPH_SCALE: f32 = 50.0;
+
+// What happened in a single animation frame. Times are in milliseconds.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameSample {
+    pub frame_time: f32,
+    pub updates: u32,
+    pub update_time: f32,
+    pub draw_time: f32,
+    // Updates that were due but skipped because the frame took too long
+    pub dropped_ticks: u32,
+}
+
+// Collects timings from the game loop, plus any counters the game reports,
+// for the overlay and for anything else that wants to read them.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metrics {
+    history: VecDeque<FrameSample>,
+    total_dropped_ticks: u64,
+    // Frames counted over the last full second
+    frame_rate: u32,
+    #[serde(skip)]
+    frames_counted: u32,
+    #[serde(skip)]
+    time_counted: f32,
+    counters: BTreeMap<String, i64>,
+    overlay_visible: bool,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn record_frame(&mut self, sample: FrameSample) {
+        self.frames_counted += 1;
+        self.time_counted += sample.frame_time;
+        if self.time_counted > 1000.0 {
+            self.frame_rate = self.frames_counted;
+            self.frames_counted = 0;
+            self.time_counted = 0.0;
+        }
+
+        self.total_dropped_ticks += u64::from(sample.dropped_ticks);
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(sample);
+    }
+
+    // Games report their own numbers, like how many obstacles are alive
+    pub fn set_counter(&mut self, name: &str, value: i64) {
+        self.counters.insert(name.into(), value);
+    }
+
+    pub fn latest(&self) -> Option<&FrameSample> {
+        self.history.back()
+    }
+
+    pub fn set_overlay_visible(&mut self, visible: bool) {
+        self.overlay_visible = visible;
+    }
+
+    pub fn toggle_overlay(&mut self) {
+        self.overlay_visible = !self.overlay_visible;
+    }
+
+    pub fn draw(&self, renderer: &dyn Renderer) {
+        if !self.overlay_visible {
+            return;
+        }
+
+        renderer.fill_rect(&OVERLAY, "rgba(255, 255, 255, 0.7)");
+
+        let latest = self.latest().cloned().unwrap_or_default();
+        let mut lines = vec![
+            format!("FPS {}", self.frame_rate),
+            format!("Updates {}", latest.updates),
+            format!(
+                "Update {:.1} Draw {:.1}",
+                latest.update_time, latest.draw_time
+            ),
+            format!("Dropped {}", self.total_dropped_ticks),
+        ];
+        lines.extend(
+            self.counters
+                .iter()
+                .map(|(name, value)| format!("{} {}", name, value)),
+        );
+
+        for (line, text) in lines.iter().enumerate() {
+            let location = Point {
+                x: OVERLAY.x() + 5,
+                y: OVERLAY.y() + 20 * (line as i16 + 1),
+            };
+            if let Err(err) = renderer.draw_text(text, &location) {
+                error!("Could not draw metrics {:#?}", err);
+            }
+        }
+
+        let graph_bottom = OVERLAY.bottom() - 5;
+        self.draw_graph(renderer, graph_bottom, "#3366CC", |sample| {
+            sample.frame_time
+        });
+        self.draw_graph(renderer, graph_bottom, "#CC3333", |sample| {
+            sample.update_time + sample.draw_time
+        });
+    }
+
+    // One bar per frame, newest on the right
+    fn draw_graph(
+        &self,
+        renderer: &dyn Renderer,
+        bottom: i16,
+        color: &str,
+        value: impl Fn(&FrameSample) -> f32,
+    ) {
+        let bar_width = OVERLAY.width / HISTORY_LENGTH as i16;
+        let left = OVERLAY.right() - bar_width * self.history.len() as i16;
+        for (index, sample) in self.history.iter().enumerate() {
+            let height = ((value(sample) / GRAPH_SCALE).min(1.0) * GRAPH_HEIGHT as f32) as i16;
+            renderer.fill_rect(
+                &Rect::new_from_x_y(
+                    left + bar_width * index as i16,
+                    bottom - height,
+                    bar_width,
+                    height,
+                ),
+                color,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{DrawCommand, RecordingRenderer};
+
+    fn sample(frame_time: f32) -> FrameSample {
+        FrameSample {
+            frame_time,
+            updates: 1,
+            ..FrameSample::default()
+        }
+    }
+
+    #[test]
+    fn frame_rate_counts_the_frames_in_the_last_second() {
+        let mut metrics = Metrics::new();
+        (0..30).for_each(|_| metrics.record_frame(sample(20.0)));
+        assert_eq!(metrics.frame_rate, 0);
+
+        (0..21).for_each(|_| metrics.record_frame(sample(20.0)));
+
+        assert_eq!(metrics.frame_rate, 51);
+    }
+
+    #[test]
+    fn history_only_keeps_the_most_recent_frames() {
+        let mut metrics = Metrics::new();
+        (0..HISTORY_LENGTH + 5).for_each(|frame| metrics.record_frame(sample(frame as f32)));
+
+        assert_eq!(metrics.history.len(), HISTORY_LENGTH);
+        assert_eq!(
+            metrics.latest().map(|sample| sample.frame_time),
+            Some((HISTORY_LENGTH + 4) as f32)
+        );
+    }
+
+    #[test]
+    fn dropped_ticks_add_up_and_counters_are_readable() {
+        let mut metrics = Metrics::new();
+        metrics.record_frame(FrameSample {
+            dropped_ticks: 3,
+            ..sample(200.0)
+        });
+        metrics.record_frame(FrameSample {
+            dropped_ticks: 2,
+            ..sample(200.0)
+        });
+        metrics.set_counter("Obstacles", 4);
+
+        assert_eq!(metrics.total_dropped_ticks, 5);
+        assert_eq!(metrics.counters.get("Obstacles"), Some(&4));
+        assert_eq!(metrics.counters.get("Missing"), None);
+    }
+
+    #[test]
+    fn the_overlay_only_draws_when_visible() {
+        let renderer = RecordingRenderer::new();
+        let mut metrics = Metrics::new();
+        metrics.record_frame(sample(16.0));
+        metrics.set_counter("Obstacles", 4);
+
+        metrics.draw(&renderer);
+        assert!(renderer.take_commands().is_empty());
+
+        metrics.toggle_overlay();
+        metrics.draw(&renderer);
+
+        assert!(renderer.commands().contains(&DrawCommand::DrawText {
+            text: "Obstacles 4".into(),
+            location: Point { x: 385, y: 190 },
+        }));
+    }
+
+    #[test]
+    fn metrics_are_readable_as_json() {
+        let mut metrics = Metrics::new();
+        metrics.record_frame(sample(16.0));
+        metrics.set_counter("Obstacles", 4);
+
+        let json: serde_json::Value = serde_json::to_value(&metrics).unwrap();
+
+        assert_eq!(json["counters"]["Obstacles"], 4);
+        assert_eq!(json["history"][0]["frameTime"], 16.0);
+        assert!(json.get("framesCounted").is_none());
+    }
+}
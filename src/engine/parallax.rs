@@ -0,0 +1,253 @@
+use super::{Point, Rect, Renderer};
+use serde::Deserialize;
+use web_sys::HtmlImageElement;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Tiling {
+    // Drawn once, where the layer starts
+    None,
+    // Repeated across the view, for hills, trees and ground strips
+    Horizontal,
+    // Repeated across and down, for skies and patterns that fill the view
+    Both,
+}
+
+impl Default for Tiling {
+    fn default() -> Self {
+        Tiling::Horizontal
+    }
+}
+
+// How a layer is written in JSON, e.g.
+// { "image": "Hills.png", "scrollFactor": 0.25, "offsetY": 120 }
+// A scroll factor of 1.0 moves with the world, 0.0 stays put on screen.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParallaxLayerDefinition {
+    pub image: String,
+    #[serde(default = "default_scroll_factor")]
+    pub scroll_factor: f32,
+    #[serde(default)]
+    pub offset_y: i16,
+    #[serde(default)]
+    pub tiling: Tiling,
+}
+
+fn default_scroll_factor() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Debug)]
+pub struct ParallaxLayer {
+    image: HtmlImageElement,
+    width: i16,
+    height: i16,
+    scroll_factor: f32,
+    offset_y: i16,
+    tiling: Tiling,
+    // Where the layer is on screen with the camera at the world's origin.
+    // Moved when the origin is, so the layer doesn't jump.
+    origin_x: f32,
+    origin_y: f32,
+}
+
+impl ParallaxLayer {
+    pub fn new(
+        image: HtmlImageElement,
+        width: i16,
+        height: i16,
+        definition: &ParallaxLayerDefinition,
+    ) -> Self {
+        ParallaxLayer {
+            image,
+            width,
+            height,
+            scroll_factor: definition.scroll_factor,
+            offset_y: definition.offset_y,
+            tiling: definition.tiling,
+            origin_x: 0.0,
+            origin_y: definition.offset_y.into(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.origin_x = 0.0;
+        self.origin_y = self.offset_y.into();
+    }
+
+    fn shift(&mut self, offset: Point) {
+        self.origin_x += f32::from(offset.x) * self.scroll_factor;
+        self.origin_y += f32::from(offset.y) * self.scroll_factor;
+
+        // Tiles repeat, so only the position within one tile matters
+        if self.tiling != Tiling::None && self.width > 0 {
+            self.origin_x = self.origin_x.rem_euclid(self.width.into());
+        }
+        if self.tiling == Tiling::Both && self.height > 0 {
+            self.origin_y = self.origin_y.rem_euclid(self.height.into());
+        }
+    }
+
+    fn draw(&self, renderer: &dyn Renderer, view: &Rect) {
+        let x = (self.origin_x - f32::from(view.x()) * self.scroll_factor).round() as i32;
+        let y = (self.origin_y - f32::from(view.y()) * self.scroll_factor).round() as i32;
+
+        let (xs, ys) = match self.tiling {
+            Tiling::None => (vec![x], vec![y]),
+            Tiling::Horizontal => (tiles(x, self.width, view.width), vec![y]),
+            Tiling::Both => (
+                tiles(x, self.width, view.width),
+                tiles(y, self.height, view.height),
+            ),
+        };
+
+        for y in &ys {
+            for x in &xs {
+                let tile = Rect::new_from_x_y(*x as i16, *y as i16, self.width, self.height);
+                if tile.intersects(&Rect::new_from_x_y(0, 0, view.width, view.height)) {
+                    renderer.draw_entire_image(&self.image, &tile.position);
+                }
+            }
+        }
+    }
+}
+
+// Where to start each copy of a tile so that copies cover 0..extent, given
+// one copy starts at start.
+fn tiles(start: i32, size: i16, extent: i16) -> Vec<i32> {
+    if size <= 0 {
+        return vec![];
+    }
+    let size = i32::from(size);
+    let first = match start.rem_euclid(size) {
+        0 => 0,
+        remainder => remainder - size,
+    };
+    (first..i32::from(extent)).step_by(size as usize).collect()
+}
+
+// Background layers that scroll at their own speeds as the camera moves,
+// drawn back to front in the order they were listed.
+#[derive(Clone, Debug, Default)]
+pub struct Parallax {
+    layers: Vec<ParallaxLayer>,
+}
+
+impl Parallax {
+    pub fn new(layers: Vec<ParallaxLayer>) -> Self {
+        Parallax { layers }
+    }
+
+    pub fn reset(&mut self) {
+        self.layers.iter_mut().for_each(ParallaxLayer::reset);
+    }
+
+    // Call alongside Camera::shift when the world's origin moves
+    pub fn shift(&mut self, offset: Point) {
+        self.layers.iter_mut().for_each(|layer| layer.shift(offset));
+    }
+
+    // Draws in screen coordinates for a camera showing view
+    pub fn draw(&self, renderer: &dyn Renderer, view: &Rect) {
+        self.layers
+            .iter()
+            .for_each(|layer| layer.draw(renderer, view));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{DrawCommand, RecordingRenderer};
+    use wasm_bindgen::{JsCast, JsValue};
+
+    fn layer(width: i16, height: i16, definition: &str) -> ParallaxLayer {
+        ParallaxLayer::new(
+            JsValue::UNDEFINED.unchecked_into(),
+            width,
+            height,
+            &serde_json::from_str(definition).unwrap(),
+        )
+    }
+
+    fn positions(parallax: &Parallax, view: &Rect) -> Vec<DrawCommand> {
+        let renderer = RecordingRenderer::new();
+        parallax.draw(&renderer, view);
+        renderer.commands()
+    }
+
+    fn at(points: &[(i16, i16)]) -> Vec<DrawCommand> {
+        points
+            .iter()
+            .map(|(x, y)| DrawCommand::DrawEntireImage(Point { x: *x, y: *y }))
+            .collect()
+    }
+
+    #[test]
+    fn layers_default_to_moving_with_the_world() {
+        let definition: ParallaxLayerDefinition =
+            serde_json::from_str(r#"{ "image": "BG.png" }"#).unwrap();
+
+        assert_eq!(definition.scroll_factor, 1.0);
+        assert_eq!(definition.offset_y, 0);
+        assert_eq!(definition.tiling, Tiling::Horizontal);
+    }
+
+    #[test]
+    fn each_layer_scrolls_at_its_own_speed_and_wraps() {
+        let parallax = Parallax::new(vec![
+            layer(250, 100, r#"{ "image": "hills", "scrollFactor": 0.5 }"#),
+            layer(250, 50, r#"{ "image": "grass", "offsetY": 550 }"#),
+        ]);
+
+        assert_eq!(
+            positions(&parallax, &Rect::new_from_x_y(300, 0, 600, 600)),
+            at(&[
+                (-150, 0),
+                (100, 0),
+                (350, 0),
+                (-50, 550),
+                (200, 550),
+                (450, 550)
+            ])
+        );
+    }
+
+    #[test]
+    fn untiled_layers_are_drawn_once_and_tiled_ones_fill_the_view() {
+        let parallax = Parallax::new(vec![
+            layer(100, 100, r#"{ "image": "sun", "tiling": "none" }"#),
+            layer(
+                400,
+                400,
+                r#"{ "image": "sky", "scrollFactor": 0.0, "tiling": "both" }"#,
+            ),
+        ]);
+
+        assert_eq!(
+            positions(&parallax, &Rect::new_from_x_y(50, 0, 600, 600)),
+            at(&[(-50, 0), (0, 0), (400, 0), (0, 400), (400, 400)])
+        );
+        assert_eq!(
+            positions(&parallax, &Rect::new_from_x_y(150, 0, 600, 600)),
+            at(&[(0, 0), (400, 0), (0, 400), (400, 400)])
+        );
+    }
+
+    #[test]
+    fn shifting_the_origin_leaves_layers_where_they_were() {
+        let mut parallax = Parallax::new(vec![
+            layer(250, 100, r#"{ "image": "hills", "scrollFactor": 0.33 }"#),
+            layer(700, 100, r#"{ "image": "trees", "scrollFactor": 0.7 }"#),
+        ]);
+        let before = positions(&parallax, &Rect::new_from_x_y(10000, 0, 600, 600));
+
+        parallax.shift(Point { x: -10000, y: 0 });
+
+        assert_eq!(
+            positions(&parallax, &Rect::new_from_x_y(0, 0, 600, 600)),
+            before
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use super::{Cell, Point, Rect, Renderer, SpriteSheet, Transform};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Emission {
+    // Everything at once. The emitter is finished when the last one dies.
+    Burst(u16),
+    // Particles per tick, which can be fractional, until the emitter is
+    // stopped
+    Continuous(f32),
+}
+
+#[derive(Clone)]
+pub enum ParticleLook {
+    // Squares size pixels across
+    Color {
+        red: u8,
+        green: u8,
+        blue: u8,
+        size: i16,
+    },
+    // Each particle is one of the cells, picked at random, scaled and spun
+    // about its centre
+    Sprites {
+        sheet: Rc<SpriteSheet>,
+        cells: Vec<Cell>,
+        scale: f32,
+    },
+}
+
+// What an emitter spawns. It holds no particles, so emitters share one
+// through an Rc rather than copying it each time they start.
+#[derive(Clone)]
+pub struct ParticleEffect {
+    look: ParticleLook,
+    emission: Emission,
+    // Ticks each particle lives for
+    lifetime: u32,
+    // Pixels per tick. Each particle picks its own from within the ranges.
+    velocity_x: (f32, f32),
+    velocity_y: (f32, f32),
+    // Added to the vertical velocity every tick
+    gravity: f32,
+    // Radians per tick, picked like the velocity
+    spin: (f32, f32),
+    // Particles go transparent as they age rather than vanishing at the end
+    fade: bool,
+}
+
+impl ParticleEffect {
+    pub fn new(look: ParticleLook, emission: Emission) -> Self {
+        ParticleEffect {
+            look,
+            emission,
+            lifetime: 30,
+            velocity_x: (0.0, 0.0),
+            velocity_y: (0.0, 0.0),
+            gravity: 0.0,
+            spin: (0.0, 0.0),
+            fade: false,
+        }
+    }
+
+    pub fn lifetime(mut self, ticks: u32) -> Self {
+        self.lifetime = ticks;
+        self
+    }
+
+    pub fn velocity(mut self, x: (f32, f32), y: (f32, f32)) -> Self {
+        self.velocity_x = x;
+        self.velocity_y = y;
+        self
+    }
+
+    pub fn gravity(mut self, gravity: f32) -> Self {
+        self.gravity = gravity;
+        self
+    }
+
+    pub fn spin(mut self, spin: (f32, f32)) -> Self {
+        self.spin = spin;
+        self
+    }
+
+    pub fn fade(mut self) -> Self {
+        self.fade = true;
+        self
+    }
+
+    fn spawn(&self, position: Point, rng: &mut ChaCha8Rng) -> Particle {
+        let cells = match &self.look {
+            ParticleLook::Sprites { cells, .. } => cells.len(),
+            ParticleLook::Color { .. } => 0,
+        };
+        Particle {
+            x: position.x.into(),
+            y: position.y.into(),
+            velocity_x: pick(rng, self.velocity_x),
+            velocity_y: pick(rng, self.velocity_y),
+            rotation: 0.0,
+            spin: pick(rng, self.spin),
+            age: 0,
+            cell: if cells > 0 {
+                rng.gen_range(0..cells)
+            } else {
+                0
+            },
+        }
+    }
+
+    fn draw(&self, renderer: &dyn Renderer, particle: &Particle) {
+        let alpha = if self.fade {
+            1.0 - particle.age as f32 / self.lifetime as f32
+        } else {
+            1.0
+        };
+        let (x, y) = (particle.x.round() as i16, particle.y.round() as i16);
+
+        match &self.look {
+            ParticleLook::Color {
+                red,
+                green,
+                blue,
+                size,
+            } => renderer.fill_rect(
+                &Rect::new_from_x_y(x - size / 2, y - size / 2, *size, *size),
+                &format!("rgba({}, {}, {}, {})", red, green, blue, alpha),
+            ),
+            ParticleLook::Sprites {
+                sheet,
+                cells,
+                scale,
+            } => {
+                if let Some(cell) = cells.get(particle.cell) {
+                    let destination = cell.destination(Point {
+                        x: x - cell.source_size.w / 2,
+                        y: y - cell.source_size.h / 2,
+                    });
+                    let centre = Point {
+                        x: destination.width / 2,
+                        y: destination.height / 2,
+                    };
+                    let transform = Transform::new()
+                        .rotate(particle.rotation, centre)
+                        .scale(*scale)
+                        .alpha(alpha);
+                    sheet.draw_transformed(renderer, cell, &destination, &transform);
+                }
+            }
+        }
+    }
+}
+
+fn pick(rng: &mut ChaCha8Rng, (low, high): (f32, f32)) -> f32 {
+    if low < high {
+        rng.gen_range(low..high)
+    } else {
+        low
+    }
+}
+
+struct Particle {
+    x: f32,
+    y: f32,
+    velocity_x: f32,
+    velocity_y: f32,
+    rotation: f32,
+    spin: f32,
+    age: u32,
+    cell: usize,
+}
+
+// Emitters and their particle lists are kept when they finish and reused by
+// the next effect, so once the pool has grown to what a game uses nothing
+// more is allocated.
+#[derive(Default)]
+struct Emitter {
+    effect: Option<Rc<ParticleEffect>>,
+    position: Point,
+    emitting: bool,
+    // Fractions of a particle a continuous emitter hasn't spawned yet
+    owed: f32,
+    particles: Vec<Particle>,
+    generation: u32,
+}
+
+impl Emitter {
+    fn is_finished(&self) -> bool {
+        !self.emitting && self.particles.is_empty()
+    }
+}
+
+// Refers to an emitter for as long as it runs. Once it finishes and its
+// slot is reused, the old id no longer matches and is ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EmitterId {
+    index: usize,
+    generation: u32,
+}
+
+pub struct ParticleSystem {
+    emitters: Vec<Emitter>,
+    rng: ChaCha8Rng,
+}
+
+impl Default for ParticleSystem {
+    fn default() -> Self {
+        ParticleSystem::new()
+    }
+}
+
+impl ParticleSystem {
+    pub fn new() -> Self {
+        ParticleSystem {
+            emitters: Vec::new(),
+            // Effects look the same every time a recording is replayed, on
+            // every release of rand
+            rng: ChaCha8Rng::seed_from_u64(0),
+        }
+    }
+
+    pub fn emit(&mut self, effect: &Rc<ParticleEffect>, position: Point) -> EmitterId {
+        let index = match self.emitters.iter().position(Emitter::is_finished) {
+            Some(index) => index,
+            None => {
+                self.emitters.push(Emitter::default());
+                self.emitters.len() - 1
+            }
+        };
+
+        let emitter = &mut self.emitters[index];
+        emitter.generation = emitter.generation.wrapping_add(1);
+        emitter.effect = Some(Rc::clone(effect));
+        emitter.position = position;
+        emitter.owed = 0.0;
+        match effect.emission {
+            Emission::Burst(count) => {
+                emitter.emitting = false;
+                for _ in 0..count {
+                    emitter
+                        .particles
+                        .push(effect.spawn(position, &mut self.rng));
+                }
+            }
+            Emission::Continuous(_) => emitter.emitting = true,
+        }
+
+        EmitterId {
+            index,
+            generation: emitter.generation,
+        }
+    }
+
+    fn emitter_mut(&mut self, id: EmitterId) -> Option<&mut Emitter> {
+        self.emitters
+            .get_mut(id.index)
+            .filter(|emitter| emitter.generation == id.generation)
+    }
+
+    // Where new particles come from. Ones already spawned carry on.
+    pub fn move_emitter(&mut self, id: EmitterId, position: Point) {
+        if let Some(emitter) = self.emitter_mut(id) {
+            emitter.position = position;
+        }
+    }
+
+    // Stops spawning and lets the live particles run out
+    pub fn stop(&mut self, id: EmitterId) {
+        if let Some(emitter) = self.emitter_mut(id) {
+            emitter.emitting = false;
+        }
+    }
+
+    pub fn update(&mut self) {
+        for emitter in &mut self.emitters {
+            let effect = match &emitter.effect {
+                Some(effect) => effect,
+                None => continue,
+            };
+
+            for particle in &mut emitter.particles {
+                particle.velocity_y += effect.gravity;
+                particle.x += particle.velocity_x;
+                particle.y += particle.velocity_y;
+                particle.rotation += particle.spin;
+                particle.age += 1;
+            }
+            emitter
+                .particles
+                .retain(|particle| particle.age < effect.lifetime);
+
+            if let (true, Emission::Continuous(rate)) = (emitter.emitting, effect.emission) {
+                emitter.owed += rate;
+                while emitter.owed >= 1.0 {
+                    emitter.owed -= 1.0;
+                    emitter
+                        .particles
+                        .push(effect.spawn(emitter.position, &mut self.rng));
+                }
+            }
+        }
+    }
+
+    // Moves every particle and emitter along with the world's origin
+    pub fn shift(&mut self, offset: Point) {
+        for emitter in &mut self.emitters {
+            emitter.position.x += offset.x;
+            emitter.position.y += offset.y;
+            for particle in &mut emitter.particles {
+                particle.x += f32::from(offset.x);
+                particle.y += f32::from(offset.y);
+            }
+        }
+    }
+
+    pub fn live_particles(&self) -> usize {
+        self.emitters
+            .iter()
+            .map(|emitter| emitter.particles.len())
+            .sum()
+    }
+
+    pub fn draw(&self, renderer: &dyn Renderer) {
+        for emitter in &self.emitters {
+            if let Some(effect) = &emitter.effect {
+                for particle in &emitter.particles {
+                    effect.draw(renderer, particle);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{DrawCommand, RecordingRenderer, Sheet, SheetRect, SheetSize};
+    use wasm_bindgen::{JsCast, JsValue};
+
+    fn grey(emission: Emission) -> ParticleEffect {
+        ParticleEffect::new(
+            ParticleLook::Color {
+                red: 128,
+                green: 128,
+                blue: 128,
+                size: 4,
+            },
+            emission,
+        )
+    }
+
+    #[test]
+    fn bursts_finish_when_their_particles_die_and_the_emitter_is_reused() {
+        let mut particles = ParticleSystem::new();
+        let burst = Rc::new(grey(Emission::Burst(5)).lifetime(2));
+
+        let first = particles.emit(&burst, Point { x: 0, y: 0 });
+        assert_eq!(particles.live_particles(), 5);
+        particles.update();
+        particles.update();
+        assert_eq!(particles.live_particles(), 0);
+
+        let second = particles.emit(&burst, Point { x: 0, y: 0 });
+        assert_eq!(second.index, first.index);
+        assert_ne!(second, first);
+        assert_eq!(particles.emitters.len(), 1);
+    }
+
+    #[test]
+    fn particles_move_fall_and_fade() {
+        let mut particles = ParticleSystem::new();
+        let effect = Rc::new(
+            grey(Emission::Burst(1))
+                .lifetime(4)
+                .velocity((2.0, 2.0), (-3.0, -3.0))
+                .gravity(1.0)
+                .fade(),
+        );
+        particles.emit(&effect, Point { x: 100, y: 100 });
+
+        particles.update();
+        particles.update();
+        let renderer = RecordingRenderer::new();
+        particles.draw(&renderer);
+
+        // Velocity y goes -2 then -1 as gravity pulls on it
+        assert_eq!(
+            renderer.commands(),
+            vec![DrawCommand::FillRect {
+                rect: Rect::new_from_x_y(102, 95, 4, 4),
+                color: "rgba(128, 128, 128, 0.5)".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn continuous_emitters_spawn_until_stopped_and_follow_their_position() {
+        let mut particles = ParticleSystem::new();
+        let trail = Rc::new(grey(Emission::Continuous(0.5)).lifetime(100));
+
+        let id = particles.emit(&trail, Point { x: 0, y: 0 });
+        (0..4).for_each(|_| particles.update());
+        assert_eq!(particles.live_particles(), 2);
+
+        particles.move_emitter(id, Point { x: 50, y: 0 });
+        (0..2).for_each(|_| particles.update());
+        particles.stop(id);
+        (0..4).for_each(|_| particles.update());
+        assert_eq!(particles.live_particles(), 3);
+
+        let renderer = RecordingRenderer::new();
+        particles.draw(&renderer);
+        assert_eq!(
+            renderer.commands().last(),
+            Some(&DrawCommand::FillRect {
+                rect: Rect::new_from_x_y(48, -2, 4, 4),
+                color: "rgba(128, 128, 128, 1)".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn sprite_particles_are_drawn_scaled_about_their_centres() {
+        let cell = Cell {
+            frame: SheetRect {
+                x: 0,
+                y: 0,
+                w: 20,
+                h: 10,
+            },
+            rotated: false,
+            trimmed: false,
+            sprite_source_size: SheetRect {
+                x: 0,
+                y: 0,
+                w: 20,
+                h: 10,
+            },
+            source_size: SheetSize { w: 20, h: 10 },
+            duration: None,
+        };
+        let sheet = Rc::new(SpriteSheet::new(
+            Sheet::default(),
+            JsValue::UNDEFINED.unchecked_into(),
+        ));
+        let effect = Rc::new(ParticleEffect::new(
+            ParticleLook::Sprites {
+                sheet,
+                cells: vec![cell],
+                scale: 0.5,
+            },
+            Emission::Burst(1),
+        ));
+        let mut particles = ParticleSystem::new();
+        particles.emit(&effect, Point { x: 100, y: 100 });
+
+        let renderer = RecordingRenderer::new();
+        particles.draw(&renderer);
+
+        assert_eq!(
+            renderer.commands(),
+            vec![DrawCommand::DrawTransformedImage {
+                frame: Rect::new_from_x_y(0, 0, 20, 10),
+                destination: Rect::new_from_x_y(90, 95, 20, 10),
+                transform: Transform::new()
+                    .rotate(0.0, Point { x: 10, y: 5 })
+                    .scale(0.5),
+            }]
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use super::{KeyChange, KeyState};
+use crate::browser;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use wasm_bindgen::JsValue;
+
+const RECORDING_STORAGE_KEY: &str = "replay";
+
+// A game's runs start and end while the loop carries on, e.g. a game over
+// followed by a new game. Each run is recorded on its own.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RunEvent {
+    // Keys are recorded rather than actions, so a run also needs the
+    // controls it was played with, in whatever form the game keeps them
+    Started {
+        seed: u64,
+        controls: serde_json::Value,
+    },
+    Ended,
+}
+
+// One run's input. Only ticks where a key changed are stored, so an idle run
+// stays tiny.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputRecording {
+    // What the run's randomness was seeded with, if the game said
+    #[serde(default, with = "seed_string")]
+    seed: Option<u64>,
+    // Which keys did what during the run
+    #[serde(default)]
+    controls: Option<serde_json::Value>,
+    // Keys already down when the run started
+    #[serde(default)]
+    held: Vec<String>,
+    ticks: Vec<RecordedTick>,
+}
+
+impl InputRecording {
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn controls(&self) -> Option<&serde_json::Value> {
+        self.controls.as_ref()
+    }
+}
+
+// Seeds use all 64 bits, more than a JavaScript number holds exactly, so
+// they are saved as strings.
+mod seed_string {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(seed: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        seed.map(|seed| seed.to_string()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|seed| seed.parse().map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct RecordedTick {
+    tick: u64,
+    changes: Vec<KeyChange>,
+}
+
+#[derive(Default)]
+pub struct InputRecorder {
+    tick: u64,
+    pending: Vec<KeyChange>,
+    recording: InputRecording,
+}
+
+impl InputRecorder {
+    pub fn new() -> Self {
+        InputRecorder::default()
+    }
+
+    pub fn record(&mut self, change: KeyChange) {
+        self.pending.push(change);
+    }
+
+    // Throws away what has been recorded and starts over for a new run, so
+    // the next tick is the run's first. Changes that haven't reached an
+    // update yet belong to the new run.
+    pub fn start(&mut self, seed: u64, controls: serde_json::Value, keystate: &KeyState) {
+        self.tick = 0;
+        self.recording = InputRecording {
+            seed: Some(seed),
+            controls: Some(controls),
+            held: keystate.held_codes(),
+            ticks: vec![],
+        };
+    }
+
+    // Stamps everything recorded since the last tick with the tick that is
+    // about to run. Returns true if the recording changed.
+    pub fn tick(&mut self) -> bool {
+        let changed = !self.pending.is_empty();
+        if changed {
+            self.recording.ticks.push(RecordedTick {
+                tick: self.tick,
+                changes: self.pending.drain(..).collect(),
+            });
+        }
+        self.tick += 1;
+        changed
+    }
+
+    pub fn recording(&self) -> &InputRecording {
+        &self.recording
+    }
+}
+
+pub struct InputReplay {
+    recording: InputRecording,
+    tick: u64,
+    next: usize,
+}
+
+impl InputReplay {
+    pub fn new(recording: InputRecording) -> Self {
+        InputReplay {
+            recording,
+            tick: 0,
+            next: 0,
+        }
+    }
+
+    // Applies the changes recorded for the tick that is about to run.
+    pub fn tick(&mut self, keystate: &mut KeyState) {
+        if self.tick == 0 {
+            self.recording
+                .held
+                .iter()
+                .for_each(|code| keystate.hold(code));
+        }
+        while let Some(recorded) = self.recording.ticks.get(self.next) {
+            if recorded.tick > self.tick {
+                break;
+            }
+            recorded
+                .changes
+                .iter()
+                .for_each(|change| keystate.apply(change));
+            self.next += 1;
+        }
+        self.tick += 1;
+    }
+}
+
+pub async fn load_recording(path: &str) -> Result<InputRecording> {
+    Ok(browser::fetch_json(path)
+        .await?
+        .into_serde::<InputRecording>()?)
+}
+
+pub fn save_recording(recording: &InputRecording) -> Result<()> {
+    let json = js_sys::JSON::stringify(&JsValue::from_serde(recording)?)
+        .map_err(|err| anyhow!("Could not stringify recording {:#?}", err))?;
+    browser::local_storage_set(RECORDING_STORAGE_KEY, &String::from(json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaying_a_recording_reproduces_the_key_state_of_every_tick() {
+        let mut recorder = InputRecorder::new();
+        let mut live = KeyState::new();
+        let mut live_ticks = vec![];
+        let frames = vec![
+            vec![],
+            vec![KeyChange::Pressed("ArrowRight".into())],
+            vec![],
+            vec![
+                KeyChange::Pressed("Space".into()),
+                KeyChange::Released("ArrowRight".into()),
+            ],
+            vec![KeyChange::Released("Space".into())],
+        ];
+        for changes in frames {
+            changes.into_iter().for_each(|change| {
+                live.apply(&change);
+                recorder.record(change);
+            });
+            recorder.tick();
+            live_ticks.push((live.is_pressed("ArrowRight"), live.is_pressed("Space")));
+        }
+
+        let mut replay = InputReplay::new(recorder.recording().clone());
+        let mut replayed = KeyState::new();
+        let replayed_ticks: Vec<(bool, bool)> = (0..live_ticks.len())
+            .map(|_| {
+                replay.tick(&mut replayed);
+                (
+                    replayed.is_pressed("ArrowRight"),
+                    replayed.is_pressed("Space"),
+                )
+            })
+            .collect();
+
+        assert_eq!(replayed_ticks, live_ticks);
+        assert_eq!(replay.next, replay.recording.ticks.len());
+    }
+
+    #[test]
+    fn each_run_is_recorded_from_its_own_first_tick() {
+        let mut recorder = InputRecorder::new();
+        let mut live = KeyState::new();
+        for _ in 0..3 {
+            recorder.record(KeyChange::Pressed("Space".into()));
+            recorder.tick();
+        }
+        live.apply(&KeyChange::Pressed("ArrowRight".into()));
+        live.tick();
+
+        recorder.start(1234, serde_json::json!({ "Jump": ["KeyW"] }), &live);
+        recorder.record(KeyChange::Pressed("ArrowDown".into()));
+        recorder.tick();
+
+        let recording = recorder.recording().clone();
+        assert_eq!(recording.seed(), Some(1234));
+        assert_eq!(
+            recording.controls(),
+            Some(&serde_json::json!({ "Jump": ["KeyW"] }))
+        );
+        assert_eq!(recording.ticks.len(), 1);
+        assert_eq!(recording.ticks[0].tick, 0);
+
+        let mut replayed = KeyState::new();
+        InputReplay::new(recording).tick(&mut replayed);
+        replayed.tick();
+        assert!(replayed.is_pressed("ArrowRight"));
+        assert!(!replayed.just_pressed("ArrowRight"));
+        assert!(replayed.just_pressed("ArrowDown"));
+    }
+
+    #[test]
+    fn seeds_survive_a_round_trip_through_json() {
+        let recording = InputRecording {
+            seed: Some(u64::MAX),
+            ..InputRecording::default()
+        };
+
+        let json = serde_json::to_string(&recording).unwrap();
+
+        assert!(json.contains("\"18446744073709551615\""));
+        assert_eq!(
+            serde_json::from_str::<InputRecording>(&json).unwrap(),
+            recording
+        );
+        assert_eq!(
+            serde_json::from_str::<InputRecording>(r#"{ "ticks": [] }"#).unwrap(),
+            InputRecording::default()
+        );
+    }
+
+    #[test]
+    fn ticks_without_changes_are_not_stored() {
+        let mut recorder = InputRecorder::new();
+
+        assert!(!recorder.tick());
+        recorder.record(KeyChange::Pressed("Space".into()));
+        assert!(recorder.tick());
+        assert!(!recorder.tick());
+
+        assert_eq!(recorder.recording().ticks.len(), 1);
+        assert_eq!(recorder.recording().ticks[0].tick, 1);
+    }
+}
@@ -0,0 +1,254 @@
+use super::{Point, Renderer};
+use anyhow::Result;
+
+pub const DEFAULT_FONT_FAMILY: &str = "Ken Future";
+
+// A CSS font, e.g. Font::new("Ken Future", 16) is "16pt Ken Future". The
+// family has to be loaded before it's drawn with, or the browser falls back
+// to another one, so list it in the asset manifest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Font {
+    family: String,
+    size: u16,
+    bold: bool,
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Font::new(DEFAULT_FONT_FAMILY, 16)
+    }
+}
+
+impl Font {
+    pub fn new(family: &str, size: u16) -> Self {
+        Font {
+            family: family.into(),
+            size,
+            bold: false,
+        }
+    }
+
+    pub fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+
+    // In points, which is all the recording renderer measures text by
+    #[cfg(test)]
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn css(&self) -> String {
+        format!(
+            "{}{}pt {}",
+            if self.bold { "bold " } else { "" },
+            self.size,
+            self.family
+        )
+    }
+}
+
+// Where the location is along the line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+impl Default for TextAlign {
+    fn default() -> Self {
+        TextAlign::Left
+    }
+}
+
+impl TextAlign {
+    pub fn css(&self) -> &'static str {
+        match self {
+            TextAlign::Left => "left",
+            TextAlign::Center => "center",
+            TextAlign::Right => "right",
+        }
+    }
+}
+
+// Where the location is up and down the line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextBaseline {
+    Top,
+    Middle,
+    Alphabetic,
+}
+
+impl Default for TextBaseline {
+    fn default() -> Self {
+        TextBaseline::Alphabetic
+    }
+}
+
+impl TextBaseline {
+    pub fn css(&self) -> &'static str {
+        match self {
+            TextBaseline::Top => "top",
+            TextBaseline::Middle => "middle",
+            TextBaseline::Alphabetic => "alphabetic",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextStyle {
+    pub font: Font,
+    pub color: String,
+    pub align: TextAlign,
+    pub baseline: TextBaseline,
+    // Lines longer than this are wrapped at spaces
+    pub max_width: Option<i16>,
+    // Distance between the baselines of wrapped lines
+    pub line_height: i16,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            font: Font::default(),
+            color: "#000000".into(),
+            align: TextAlign::Left,
+            baseline: TextBaseline::Alphabetic,
+            max_width: None,
+            line_height: 25,
+        }
+    }
+}
+
+impl TextStyle {
+    pub fn new(font: Font) -> Self {
+        TextStyle {
+            font,
+            ..TextStyle::default()
+        }
+    }
+
+    pub fn color(mut self, color: &str) -> Self {
+        self.color = color.into();
+        self
+    }
+
+    pub fn align(mut self, align: TextAlign) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn baseline(mut self, baseline: TextBaseline) -> Self {
+        self.baseline = baseline;
+        self
+    }
+
+    pub fn wrap(mut self, max_width: i16, line_height: i16) -> Self {
+        self.max_width = Some(max_width);
+        self.line_height = line_height;
+        self
+    }
+
+    // Draws text a line at a time, wrapping it first if the style says to
+    pub fn draw(&self, renderer: &dyn Renderer, text: &str, location: &Point) -> Result<()> {
+        let lines = match self.max_width {
+            Some(max_width) => wrap_lines(text, max_width, |line| {
+                renderer.measure_text(line, &self.font)
+            })?,
+            None => vec![text.to_string()],
+        };
+
+        for (index, line) in lines.iter().enumerate() {
+            let location = Point {
+                x: location.x,
+                y: location.y + self.line_height * index as i16,
+            };
+            renderer.draw_styled_text(line, &location, self)?;
+        }
+        Ok(())
+    }
+}
+
+// Breaks text at spaces so no line is wider than max_width, except single
+// words that don't fit anywhere. Newlines always break.
+pub fn wrap_lines(
+    text: &str,
+    max_width: i16,
+    measure: impl Fn(&str) -> Result<i16>,
+) -> Result<Vec<String>> {
+    let mut lines = vec![];
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if line.is_empty() || measure(&candidate)? <= max_width {
+                line = candidate;
+            } else {
+                lines.push(std::mem::replace(&mut line, word.to_string()));
+            }
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{DrawCommand, RecordingRenderer};
+
+    #[test]
+    fn fonts_describe_themselves_in_css() {
+        assert_eq!(Font::default().css(), "16pt Ken Future");
+        assert_eq!(Font::new("serif", 12).bold().css(), "bold 12pt serif");
+    }
+
+    #[test]
+    fn long_lines_wrap_at_spaces() {
+        let measure = |line: &str| Ok(line.len() as i16 * 10);
+
+        assert_eq!(
+            wrap_lines("Walk the dog\nagain and again", 100, measure).unwrap(),
+            vec!["Walk the", "dog", "again and", "again"]
+        );
+        assert_eq!(
+            wrap_lines("Unbreakable", 50, measure).unwrap(),
+            vec!["Unbreakable"]
+        );
+    }
+
+    #[test]
+    fn wrapped_text_is_drawn_a_line_at_a_time() {
+        let renderer = RecordingRenderer::new();
+        // The recording renderer measures every character as the font's size
+        let style = TextStyle::new(Font::new("serif", 10))
+            .align(TextAlign::Center)
+            .wrap(100, 20);
+
+        style
+            .draw(&renderer, "Game over again", &Point { x: 300, y: 100 })
+            .unwrap();
+
+        assert_eq!(
+            renderer.commands(),
+            vec![
+                DrawCommand::DrawStyledText {
+                    text: "Game over".into(),
+                    location: Point { x: 300, y: 100 },
+                    style: style.clone(),
+                },
+                DrawCommand::DrawStyledText {
+                    text: "again".into(),
+                    location: Point { x: 300, y: 120 },
+                    style,
+                },
+            ]
+        );
+    }
+}
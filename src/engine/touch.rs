@@ -0,0 +1,254 @@
+use super::{KeyChange, Point, Rect, Renderer};
+use std::collections::HashMap;
+
+// Gestures are reported as virtual key codes, so an ActionMap can bind them
+// like any other key.
+pub const TAP: &str = "Tap";
+pub const SWIPE_UP: &str = "SwipeUp";
+pub const SWIPE_DOWN: &str = "SwipeDown";
+pub const SWIPE_LEFT: &str = "SwipeLeft";
+pub const SWIPE_RIGHT: &str = "SwipeRight";
+
+// Anything shorter than this is a tap, no matter which way it moved
+const SWIPE_DISTANCE: i16 = 30;
+
+// An on-screen button that holds its key code down for as long as it is
+// touched. Buttons are only drawn once the player has touched the screen.
+#[derive(Clone, Debug)]
+pub struct VirtualButton {
+    rect: Rect,
+    code: String,
+    label: String,
+}
+
+impl VirtualButton {
+    pub fn new(rect: Rect, code: &str, label: &str) -> Self {
+        VirtualButton {
+            rect,
+            code: code.into(),
+            label: label.into(),
+        }
+    }
+
+    fn draw(&self, renderer: &dyn Renderer) {
+        renderer.fill_rect(&self.rect, "rgba(255, 255, 255, 0.3)");
+        if let Err(err) = renderer.draw_text(
+            &self.label,
+            &Point {
+                x: self.rect.x() + 10,
+                y: self.rect.y() + self.rect.height / 2,
+            },
+        ) {
+            error!("Could not draw virtual button {:#?}", err);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PointerChange {
+    Down {
+        id: i32,
+        position: Point,
+        // "mouse", "pen" or "touch"
+        pointer_type: String,
+    },
+    Up {
+        id: i32,
+        position: Point,
+    },
+    Cancel {
+        id: i32,
+    },
+}
+
+enum Pointer {
+    Gesture(Point),
+    Button(String),
+}
+
+#[derive(Default)]
+pub struct TouchInput {
+    buttons: Vec<VirtualButton>,
+    pointers: HashMap<i32, Pointer>,
+    // A gesture is over by the time it is recognized, so its code is pressed
+    // for exactly one update and then released.
+    pulses: Vec<String>,
+    releasing: Vec<String>,
+    touch_detected: bool,
+}
+
+impl TouchInput {
+    pub fn new() -> Self {
+        TouchInput::default()
+    }
+
+    pub fn set_buttons(&mut self, buttons: Vec<VirtualButton>) {
+        self.buttons = buttons;
+    }
+
+    pub fn apply(&mut self, change: PointerChange) -> Vec<KeyChange> {
+        match change {
+            PointerChange::Down {
+                id,
+                position,
+                pointer_type,
+            } => {
+                // Clicks are for the page, e.g. refocusing the canvas, and
+                // mice have keys to play with
+                if pointer_type != "touch" && pointer_type != "pen" {
+                    return vec![];
+                }
+                self.touch_detected = true;
+                match self.button_at(position) {
+                    Some(code) => {
+                        self.pointers.insert(id, Pointer::Button(code.clone()));
+                        vec![KeyChange::Pressed(code)]
+                    }
+                    None => {
+                        self.pointers.insert(id, Pointer::Gesture(position));
+                        vec![]
+                    }
+                }
+            }
+            PointerChange::Up { id, position } => match self.pointers.remove(&id) {
+                Some(Pointer::Button(code)) => vec![KeyChange::Released(code)],
+                Some(Pointer::Gesture(start)) => {
+                    let code = classify(start, position).to_string();
+                    self.pulses.push(code.clone());
+                    vec![KeyChange::Pressed(code)]
+                }
+                None => vec![],
+            },
+            PointerChange::Cancel { id } => match self.pointers.remove(&id) {
+                Some(Pointer::Button(code)) => vec![KeyChange::Released(code)],
+                _ => vec![],
+            },
+        }
+    }
+
+    // Called once before every update. Releases the gestures the last update
+    // saw, and holds the ones that arrived since for this one.
+    pub fn tick(&mut self) -> Vec<KeyChange> {
+        let released = std::mem::replace(&mut self.releasing, std::mem::take(&mut self.pulses));
+        released.into_iter().map(KeyChange::Released).collect()
+    }
+
+    pub fn draw(&self, renderer: &dyn Renderer) {
+        if self.touch_detected {
+            self.buttons.iter().for_each(|button| button.draw(renderer));
+        }
+    }
+
+    fn button_at(&self, position: Point) -> Option<String> {
+        self.buttons
+            .iter()
+            .find(|button| button.rect.contains(position))
+            .map(|button| button.code.clone())
+    }
+}
+
+fn classify(start: Point, end: Point) -> &'static str {
+    let dx = end.x - start.x;
+    let dy = end.y - start.y;
+    if dx.abs().max(dy.abs()) < SWIPE_DISTANCE {
+        TAP
+    } else if dy.abs() >= dx.abs() {
+        if dy > 0 {
+            SWIPE_DOWN
+        } else {
+            SWIPE_UP
+        }
+    } else if dx > 0 {
+        SWIPE_RIGHT
+    } else {
+        SWIPE_LEFT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn down(id: i32, x: i16, y: i16) -> PointerChange {
+        PointerChange::Down {
+            id,
+            position: Point { x, y },
+            pointer_type: "touch".into(),
+        }
+    }
+
+    fn up(id: i32, x: i16, y: i16) -> PointerChange {
+        PointerChange::Up {
+            id,
+            position: Point { x, y },
+        }
+    }
+
+    #[test]
+    fn short_movements_are_taps_and_long_ones_are_swipes() {
+        let start = Point { x: 100, y: 100 };
+
+        assert_eq!(classify(start, Point { x: 110, y: 95 }), TAP);
+        assert_eq!(classify(start, Point { x: 105, y: 160 }), SWIPE_DOWN);
+        assert_eq!(classify(start, Point { x: 90, y: 40 }), SWIPE_UP);
+        assert_eq!(classify(start, Point { x: 180, y: 120 }), SWIPE_RIGHT);
+        assert_eq!(classify(start, Point { x: 20, y: 100 }), SWIPE_LEFT);
+    }
+
+    #[test]
+    fn a_gesture_is_pressed_for_one_update() {
+        let mut touch = TouchInput::new();
+
+        assert!(touch.apply(down(1, 100, 100)).is_empty());
+        assert_eq!(
+            touch.apply(up(1, 100, 200)),
+            vec![KeyChange::Pressed(SWIPE_DOWN.into())]
+        );
+
+        assert!(touch.tick().is_empty());
+        assert_eq!(touch.tick(), vec![KeyChange::Released(SWIPE_DOWN.into())]);
+        assert!(touch.tick().is_empty());
+    }
+
+    #[test]
+    fn mouse_clicks_are_not_gestures() {
+        let mut touch = TouchInput::new();
+        touch.set_buttons(vec![VirtualButton::new(
+            Rect::new_from_x_y(0, 500, 100, 100),
+            "VirtualJump",
+            "Jump",
+        )]);
+        let click = |x, y| PointerChange::Down {
+            id: 1,
+            position: Point { x, y },
+            pointer_type: "mouse".into(),
+        };
+
+        assert!(touch.apply(click(300, 300)).is_empty());
+        assert!(touch.apply(up(1, 300, 300)).is_empty());
+        assert!(touch.apply(click(50, 550)).is_empty());
+        assert!(touch.apply(up(1, 50, 550)).is_empty());
+        assert!(touch.tick().is_empty());
+        assert!(!touch.touch_detected);
+    }
+
+    #[test]
+    fn virtual_buttons_are_held_until_the_pointer_lifts() {
+        let mut touch = TouchInput::new();
+        touch.set_buttons(vec![VirtualButton::new(
+            Rect::new_from_x_y(0, 500, 100, 100),
+            "VirtualJump",
+            "Jump",
+        )]);
+
+        assert_eq!(
+            touch.apply(down(1, 50, 550)),
+            vec![KeyChange::Pressed("VirtualJump".into())]
+        );
+        assert!(touch.tick().is_empty());
+        assert_eq!(
+            touch.apply(up(1, 300, 300)),
+            vec![KeyChange::Released("VirtualJump".into())]
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use super::Point;
+
+// Curves from https://easings.net, mapping progress from 0.0 to 1.0 onto how
+// far between the two values to be. Back overshoots on the way. Add others
+// from there as effects need them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    QuadIn,
+    QuadOut,
+    BackOut,
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Easing::Linear
+    }
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::QuadIn => t * t,
+            Easing::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::BackOut => {
+                const OVERSHOOT: f32 = 1.70158;
+                1.0 + (OVERSHOOT + 1.0) * (t - 1.0).powi(3) + OVERSHOOT * (t - 1.0).powi(2)
+            }
+        }
+    }
+}
+
+// Anything a tween can move between
+pub trait Tweenable: Copy {
+    fn interpolate(from: Self, to: Self, progress: f32) -> Self;
+}
+
+impl Tweenable for f32 {
+    fn interpolate(from: f32, to: f32, progress: f32) -> f32 {
+        from + (to - from) * progress
+    }
+}
+
+impl Tweenable for Point {
+    fn interpolate(from: Point, to: Point, progress: f32) -> Point {
+        Point {
+            x: from.x + (f32::from(to.x - from.x) * progress).round() as i16,
+            y: from.y + (f32::from(to.y - from.y) * progress).round() as i16,
+        }
+    }
+}
+
+// A value moving from one thing to another over a number of game ticks.
+// Advance it once per update; it only ever moves in whole ticks, so it plays
+// out the same way every time.
+pub struct Tween<T> {
+    from: T,
+    to: T,
+    // Ticks spent at from before moving
+    delay: u32,
+    duration: u32,
+    easing: Easing,
+    elapsed: u32,
+    on_complete: Option<Box<dyn FnOnce()>>,
+}
+
+impl<T: Tweenable> Tween<T> {
+    pub fn new(from: T, to: T, duration: u32) -> Self {
+        Tween {
+            from,
+            to,
+            delay: 0,
+            duration,
+            easing: Easing::Linear,
+            elapsed: 0,
+            on_complete: None,
+        }
+    }
+
+    pub fn delay(mut self, ticks: u32) -> Self {
+        self.delay = ticks;
+        self
+    }
+
+    pub fn easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    // Called once, by the update that reaches the end
+    pub fn on_complete(mut self, on_complete: impl FnOnce() + 'static) -> Self {
+        self.on_complete = Some(Box::new(on_complete));
+        self
+    }
+
+    // A tween with nothing to play is finished from the start, and
+    // completes on its first update
+    pub fn update(&mut self) {
+        if !self.is_finished() {
+            self.elapsed += 1;
+        }
+        if self.is_finished() {
+            if let Some(on_complete) = self.on_complete.take() {
+                on_complete();
+            }
+        }
+    }
+
+    // Skips straight to the end, for when there's no time to watch it play
+    pub fn finish(&mut self) {
+        if !self.is_finished() {
+            self.elapsed = self.delay + self.duration;
+            if let Some(on_complete) = self.on_complete.take() {
+                on_complete();
+            }
+        }
+    }
+
+    pub fn value(&self) -> T {
+        T::interpolate(self.from, self.to, self.easing.apply(self.progress()))
+    }
+
+    // How far through the tween is, from 0.0 until the delay is over to 1.0
+    pub fn progress(&self) -> f32 {
+        match self.duration {
+            0 if self.elapsed >= self.delay => 1.0,
+            0 => 0.0,
+            duration => self.elapsed.saturating_sub(self.delay) as f32 / duration as f32,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.delay + self.duration
+    }
+}
+
+// Tweens played one after another, the value held at the end of one until
+// the next starts moving.
+pub struct Sequence<T> {
+    tweens: Vec<Tween<T>>,
+    current: usize,
+}
+
+impl<T: Tweenable> Sequence<T> {
+    pub fn new(first: Tween<T>) -> Self {
+        Sequence {
+            tweens: vec![first],
+            current: 0,
+        }
+    }
+
+    pub fn then(mut self, tween: Tween<T>) -> Self {
+        self.tweens.push(tween);
+        self
+    }
+
+    pub fn update(&mut self) {
+        if let Some(tween) = self.tweens.get_mut(self.current) {
+            tween.update();
+            if tween.is_finished() {
+                self.current += 1;
+            }
+        }
+    }
+
+    pub fn value(&self) -> T {
+        let index = self.current.min(self.tweens.len() - 1);
+        self.tweens[index].value()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current >= self.tweens.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+
+    const EASINGS: [Easing; 4] = [
+        Easing::Linear,
+        Easing::QuadIn,
+        Easing::QuadOut,
+        Easing::BackOut,
+    ];
+
+    fn close(left: f32, right: f32) -> bool {
+        (left - right).abs() < 0.0001
+    }
+
+    #[test]
+    fn every_easing_starts_at_zero_and_ends_at_one() {
+        for easing in EASINGS {
+            assert!(close(easing.apply(0.0), 0.0), "{:?} starts off", easing);
+            assert!(close(easing.apply(1.0), 1.0), "{:?} ends off", easing);
+        }
+        assert!(close(Easing::QuadIn.apply(0.5), 0.25));
+        assert!(close(Easing::QuadOut.apply(0.5), 0.75));
+        assert!(Easing::BackOut.apply(0.8) > 1.0);
+    }
+
+    #[test]
+    fn tweens_wait_out_their_delay_then_move_over_their_duration() {
+        let mut tween = Tween::new(10.0, 20.0, 4).delay(2);
+
+        let values: Vec<f32> = (0..8)
+            .map(|_| {
+                let value = tween.value();
+                tween.update();
+                value
+            })
+            .collect();
+
+        assert_eq!(values, vec![10.0, 10.0, 10.0, 12.5, 15.0, 17.5, 20.0, 20.0]);
+        assert!(tween.is_finished());
+    }
+
+    #[test]
+    fn completion_is_called_once_when_the_tween_ends() {
+        let completions = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&completions);
+        let mut tween = Tween::new(0.0, 1.0, 2).on_complete(move || counter.set(counter.get() + 1));
+
+        tween.update();
+        assert_eq!(completions.get(), 0);
+        (0..3).for_each(|_| tween.update());
+        assert_eq!(completions.get(), 1);
+    }
+
+    #[test]
+    fn empty_tweens_complete_on_their_first_update() {
+        let completions = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&completions);
+        let mut sequence = Sequence::new(
+            Tween::new(0.0, 0.0, 0).on_complete(move || counter.set(counter.get() + 1)),
+        )
+        .then(Tween::new(0.0, 1.0, 1));
+
+        assert_eq!(completions.get(), 0);
+        sequence.update();
+        assert_eq!(completions.get(), 1);
+        sequence.update();
+        sequence.update();
+        assert_eq!(completions.get(), 1);
+        assert!(sequence.is_finished());
+    }
+
+    #[test]
+    fn finishing_jumps_to_the_end_and_completes() {
+        let completions = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&completions);
+        let mut tween = Tween::new(0.0, 8.0, 4)
+            .delay(2)
+            .on_complete(move || counter.set(counter.get() + 1));
+
+        tween.finish();
+        tween.finish();
+        tween.update();
+
+        assert_eq!(tween.value(), 8.0);
+        assert!(tween.is_finished());
+        assert_eq!(completions.get(), 1);
+    }
+
+    #[test]
+    fn sequences_play_each_tween_in_turn() {
+        let mut sequence =
+            Sequence::new(Tween::new(Point { x: 0, y: 0 }, Point { x: 0, y: -30 }, 3))
+                .then(Tween::new(Point { x: 0, y: -30 }, Point { x: 10, y: -30 }, 2).delay(1));
+
+        let mut values = vec![sequence.value()];
+        while !sequence.is_finished() {
+            sequence.update();
+            values.push(sequence.value());
+        }
+
+        assert_eq!(
+            values,
+            vec![
+                Point { x: 0, y: 0 },
+                Point { x: 0, y: -10 },
+                Point { x: 0, y: -20 },
+                Point { x: 0, y: -30 },
+                Point { x: 0, y: -30 },
+                Point { x: 5, y: -30 },
+                Point { x: 10, y: -30 },
+            ]
+        );
+    }
+}
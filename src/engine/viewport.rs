@@ -0,0 +1,189 @@
+use super::{Point, Rect};
+use crate::browser;
+use anyhow::{anyhow, Result};
+use std::{cell::Cell, rc::Rc};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+
+// Games draw in this space whatever size the canvas ends up on screen
+pub const LOGICAL_WIDTH: i16 = 600;
+pub const LOGICAL_HEIGHT: i16 = 600;
+pub const LOGICAL_AREA: Rect = Rect::new_from_x_y(0, 0, LOGICAL_WIDTH, LOGICAL_HEIGHT);
+
+// How the logical space fits on a canvas of some size. It is scaled up as
+// far as it goes without stretching and centred, leaving bars on the sides
+// or top and bottom that nothing draws into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    // The canvas' size on the page, in CSS pixels
+    css_width: f64,
+    css_height: f64,
+    // Device pixels per CSS pixel
+    pixel_ratio: f64,
+    // CSS pixels per logical pixel
+    scale: f64,
+    // Where the logical space starts on the canvas, in CSS pixels
+    offset_x: f64,
+    offset_y: f64,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport::fit(LOGICAL_WIDTH.into(), LOGICAL_HEIGHT.into(), 1.0)
+    }
+}
+
+impl Viewport {
+    pub fn fit(css_width: f64, css_height: f64, pixel_ratio: f64) -> Self {
+        let scale =
+            (css_width / f64::from(LOGICAL_WIDTH)).min(css_height / f64::from(LOGICAL_HEIGHT));
+        Viewport {
+            css_width,
+            css_height,
+            pixel_ratio,
+            scale,
+            offset_x: (css_width - f64::from(LOGICAL_WIDTH) * scale) / 2.0,
+            offset_y: (css_height - f64::from(LOGICAL_HEIGHT) * scale) / 2.0,
+        }
+    }
+
+    // The canvas' width and height attributes, one pixel per device pixel
+    pub fn backing_size(&self) -> (u32, u32) {
+        (
+            (self.css_width * self.pixel_ratio).round() as u32,
+            (self.css_height * self.pixel_ratio).round() as u32,
+        )
+    }
+
+    // Scale and translation from logical coordinates to the backing store
+    pub fn transform(&self) -> (f64, f64, f64) {
+        (
+            self.scale * self.pixel_ratio,
+            self.offset_x * self.pixel_ratio,
+            self.offset_y * self.pixel_ratio,
+        )
+    }
+
+    // Maps a position on the canvas in CSS pixels, like a pointer event's
+    // offset, back to logical coordinates.
+    pub fn to_logical(self, css_x: f64, css_y: f64) -> Point {
+        Point {
+            x: ((css_x - self.offset_x) / self.scale).round() as i16,
+            y: ((css_y - self.offset_y) / self.scale).round() as i16,
+        }
+    }
+}
+
+// The canvas the loop draws on. Each frame it is resized to fill its
+// container at the screen's pixel density, and the context is set up so
+// drawing in logical coordinates lands in the letterboxed area.
+pub struct Display {
+    canvas: HtmlCanvasElement,
+    context: CanvasRenderingContext2d,
+    viewport: Rc<Cell<Viewport>>,
+}
+
+impl Display {
+    pub fn new() -> Result<Self> {
+        Ok(Display {
+            canvas: browser::canvas()?,
+            context: browser::context()?,
+            viewport: Rc::new(Cell::new(Viewport::default())),
+        })
+    }
+
+    // Where the loop listens for input
+    pub fn canvas(&self) -> &HtmlCanvasElement {
+        &self.canvas
+    }
+
+    pub fn context(&self) -> CanvasRenderingContext2d {
+        self.context.clone()
+    }
+
+    // Shared with the pointer listeners, which map through it
+    pub fn viewport(&self) -> Rc<Cell<Viewport>> {
+        Rc::clone(&self.viewport)
+    }
+
+    pub fn begin_frame(&self) -> Result<()> {
+        self.resize()?;
+
+        let (scale, x, y) = self.viewport.get().transform();
+        self.context
+            .set_transform(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+            .map_err(|err| anyhow!("Could not reset the canvas transform {:#?}", err))?;
+        self.context.clear_rect(
+            0.0,
+            0.0,
+            self.canvas.width().into(),
+            self.canvas.height().into(),
+        );
+        self.context
+            .set_transform(scale, 0.0, 0.0, scale, x, y)
+            .map_err(|err| anyhow!("Could not set the canvas transform {:#?}", err))?;
+
+        // Keep anything drawn past the edges out of the bars
+        self.context.save();
+        self.context.begin_path();
+        self.context
+            .rect(0.0, 0.0, LOGICAL_WIDTH.into(), LOGICAL_HEIGHT.into());
+        self.context.clip();
+        Ok(())
+    }
+
+    pub fn end_frame(&self) {
+        self.context.restore();
+    }
+
+    fn resize(&self) -> Result<()> {
+        let viewport = Viewport::fit(
+            self.canvas.client_width().into(),
+            self.canvas.client_height().into(),
+            browser::device_pixel_ratio()?,
+        );
+        if viewport == self.viewport.get() && self.canvas.width() == viewport.backing_size().0 {
+            return Ok(());
+        }
+
+        let (width, height) = viewport.backing_size();
+        self.canvas.set_width(width);
+        self.canvas.set_height(height);
+        self.viewport.set(viewport);
+
+        // The UI is laid out in logical pixels too
+        browser::transform_ui(viewport.scale, viewport.offset_x, viewport.offset_y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_wide_canvas_is_pillarboxed() {
+        let viewport = Viewport::fit(1600.0, 1200.0, 1.0);
+
+        assert_eq!(viewport.transform(), (2.0, 200.0, 0.0));
+        assert_eq!(viewport.backing_size(), (1600, 1200));
+    }
+
+    #[test]
+    fn a_tall_canvas_is_letterboxed_at_the_pixel_ratio() {
+        let viewport = Viewport::fit(300.0, 500.0, 2.0);
+
+        assert_eq!(viewport.transform(), (1.0, 0.0, 200.0));
+        assert_eq!(viewport.backing_size(), (600, 1000));
+    }
+
+    #[test]
+    fn pointer_positions_map_back_to_logical_space() {
+        let viewport = Viewport::fit(1600.0, 1200.0, 2.0);
+
+        assert_eq!(viewport.to_logical(200.0, 0.0), Point { x: 0, y: 0 });
+        assert_eq!(
+            viewport.to_logical(1400.0, 1200.0),
+            Point { x: 600, y: 600 }
+        );
+        assert_eq!(viewport.to_logical(100.0, 10.0), Point { x: -50, y: 5 });
+    }
+}
@@ -1,31 +1,357 @@
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc};
 
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use futures::channel::mpsc::UnboundedReceiver;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
 use web_sys::HtmlImageElement;
 
 use self::red_hat_boy_states::*;
 use crate::{
     browser,
     engine::{
-        self, Audio, Cell, Game, Image, KeyState, Point, Rect, Renderer, Sheet, Sound, SpriteSheet,
+        self, ActionMap, ActionState, AssetLoader, Audio, Camera, Cell, Clip, DrawList, Easing,
+        Emission, EmitterId, Font, Game, HitStop, Image, ImageHandle, InputRecording, KeyState,
+        Layer, Metrics, Parallax, ParallaxHandle, ParticleEffect, ParticleLook, ParticleSystem,
+        Point, Rect, Renderer, RunEvent, Sequence, Sound, SoundHandle, SpriteSheet,
+        SpriteSheetHandle, TextAlign, TextBaseline, TextStyle, Tween, VirtualButton,
     },
     segments::*,
 };
 
-const HEIGHT: i16 = 600;
+const WIDTH: i16 = engine::LOGICAL_WIDTH;
+const HEIGHT: i16 = engine::LOGICAL_HEIGHT;
 const TIMELINE_MINIMUM: i16 = 1000;
+const REBASE_DISTANCE: i16 = 10000;
 const OBSTACLE_BUFFER: i16 = 20;
+// Depths within a layer. The boy runs in front of the platforms, and the
+// pause screen covers the debug overlay.
+const OBSTACLE_Z: i16 = 0;
+const BOY_Z: i16 = 1;
+const POPUP_Z: i16 = 1;
+const FADE_Z: i16 = -1;
+const DEBUG_Z: i16 = 0;
+const SCORE_Z: i16 = 0;
+const PAUSED_Z: i16 = 1;
+const SCORE_POPUP_INTERVAL: u16 = 500;
+const SCORE_POPUP_SIZE: f32 = 20.0;
+const PAUSE_SLIDE_TICKS: u32 = 20;
+const GAME_OVER_FADE_TICKS: u32 = 45;
+const GAME_OVER_FADE_ALPHA: f32 = 0.6;
+const CONTROLS_STORAGE_KEY: &str = "controls";
+const SETTINGS_STORAGE_KEY: &str = "settings";
+// How hard getting knocked out shakes the screen, and how many updates
+// everything holds still for first
+const KNOCKOUT_TRAUMA: f32 = 0.7;
+const KNOCKOUT_FREEZE_TICKS: u32 = 6;
+const ASSET_MANIFEST: &str = "assets.json";
+const BACKGROUND: ParallaxHandle = ParallaxHandle::new("walk");
+const STONE: ImageHandle = ImageHandle::new("stone");
+const RHB: SpriteSheetHandle = SpriteSheetHandle::new("rhb");
+const TILES: SpriteSheetHandle = SpriteSheetHandle::new("tiles");
+const JUMP_SOUND: SoundHandle = SoundHandle::new("jump");
+const MUSIC: SoundHandle = SoundHandle::new("music");
+const VIRTUAL_JUMP: &str = "VirtualJump";
+const VIRTUAL_SLIDE: &str = "VirtualSlide";
+// Bits of platform that fly off when the boy hits one
+const DEBRIS_SPRITES: [&str; 3] = ["13.png", "14.png", "15.png"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Run,
+    Jump,
+    Slide,
+    Pause,
+    ToggleDebug,
+    ToggleShake,
+}
+
+type Controls<'a> = ActionState<'a, Action>;
+
+fn default_controls() -> ActionMap<Action> {
+    let mut controls = ActionMap::new();
+    controls.bind(Action::Run, "ArrowRight");
+    controls.bind(Action::Run, engine::TAP);
+    controls.bind(Action::Run, engine::GAMEPAD_RIGHT);
+    controls.bind(Action::Jump, "Space");
+    controls.bind(Action::Jump, "ArrowUp");
+    controls.bind(Action::Jump, engine::TAP);
+    controls.bind(Action::Jump, VIRTUAL_JUMP);
+    controls.bind(Action::Jump, "GamepadA");
+    controls.bind(Action::Jump, engine::GAMEPAD_UP);
+    controls.bind(Action::Slide, "ArrowDown");
+    controls.bind(Action::Slide, engine::SWIPE_DOWN);
+    controls.bind(Action::Slide, VIRTUAL_SLIDE);
+    controls.bind(Action::Slide, "GamepadB");
+    controls.bind(Action::Slide, engine::GAMEPAD_DOWN);
+    controls.bind(Action::Pause, "KeyP");
+    controls.bind(Action::Pause, "Escape");
+    controls.bind(Action::Pause, "GamepadStart");
+    controls.bind(Action::ToggleDebug, "KeyH");
+    controls.bind(Action::ToggleShake, "KeyK");
+    controls
+}
+
+struct Bindings {
+    // Only what the player changed, which is all that's saved, so keys
+    // added to the defaults later still reach players who rebound others
+    rebound: ActionMap<Action>,
+    // The defaults with the player's changes on top
+    all: ActionMap<Action>,
+    // Played back from a recording, which has to keep the keys it was
+    // recorded with
+    recorded: bool,
+}
+
+impl Bindings {
+    fn new(rebound: ActionMap<Action>) -> Self {
+        let mut all = default_controls();
+        all.override_with(&rebound);
+        Bindings {
+            rebound,
+            all,
+            recorded: false,
+        }
+    }
+}
+
+// The controls in play, shared with the page so players can rebind them
+// while the game runs
+#[derive(Clone)]
+pub struct SharedControls(Rc<RefCell<Bindings>>);
+
+impl Default for SharedControls {
+    fn default() -> Self {
+        SharedControls(Rc::new(RefCell::new(Bindings::new(ActionMap::new()))))
+    }
+}
+
+impl SharedControls {
+    // The controls a recording was played with. Recordings that didn't save
+    // any were played with the defaults.
+    fn recorded(controls: Option<&serde_json::Value>) -> Result<Self> {
+        let mut bindings = match controls {
+            Some(controls) => Bindings::new(serde_json::from_value(controls.clone())?),
+            None => Bindings::new(ActionMap::new()),
+        };
+        bindings.recorded = true;
+        Ok(SharedControls(Rc::new(RefCell::new(bindings))))
+    }
+
+    // Players can rebind their controls, so apply whatever they saved last
+    // time over the defaults.
+    fn load(&self) {
+        if self.0.borrow().recorded {
+            return;
+        }
+        let rebound = match ActionMap::load(CONTROLS_STORAGE_KEY) {
+            Ok(rebound) => rebound.unwrap_or_else(ActionMap::new),
+            Err(err) => {
+                error!("Could not load saved controls {:#?}", err);
+                ActionMap::new()
+            }
+        };
+        *self.0.borrow_mut() = Bindings::new(rebound);
+    }
+
+    pub fn rebind(&self, action: Action, codes: &[&str]) -> Result<()> {
+        let mut bindings = self.0.borrow_mut();
+        if bindings.recorded {
+            return Err(anyhow!("Controls can't be rebound during a replay"));
+        }
+        bindings.rebound.rebind(action, codes);
+        bindings.all.rebind(action, codes);
+        bindings.rebound.save(CONTROLS_STORAGE_KEY)
+    }
+
+    // What a recording needs to press the same actions with the same keys
+    fn to_recording(&self) -> serde_json::Value {
+        serde_json::to_value(&self.0.borrow().all).unwrap_or_else(|err| {
+            error!("Could not record the controls {:#?}", err);
+            serde_json::Value::Null
+        })
+    }
+
+    fn with_state<R>(&self, keystate: &KeyState, f: impl FnOnce(&Controls) -> R) -> R {
+        f(&self.0.borrow().all.state(keystate))
+    }
+
+    fn just_pressed(&self, keystate: &KeyState, action: Action) -> bool {
+        self.with_state(keystate, |controls| controls.just_pressed(action))
+    }
+}
+
+// Player preferences that outlast a game, kept in local storage
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    pub screen_shake: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { screen_shake: true }
+    }
+}
+
+impl Settings {
+    fn load(storage_key: &str) -> Result<Option<Self>> {
+        match browser::local_storage_get(storage_key)? {
+            Some(json) => {
+                let value = js_sys::JSON::parse(&json)
+                    .map_err(|err| anyhow!("Could not parse saved settings {:#?}", err))?;
+                Ok(Some(value.into_serde::<Settings>()?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, storage_key: &str) -> Result<()> {
+        let json = js_sys::JSON::stringify(&JsValue::from_serde(self)?)
+            .map_err(|err| anyhow!("Could not stringify settings {:#?}", err))?;
+        browser::local_storage_set(storage_key, &String::from(json))
+    }
+}
+
+fn load_settings() -> Settings {
+    match Settings::load(SETTINGS_STORAGE_KEY) {
+        Ok(settings) => settings.unwrap_or_default(),
+        Err(err) => {
+            error!("Could not load saved settings {:#?}", err);
+            Settings::default()
+        }
+    }
+}
 
 pub struct WalkTheDog {
     machine: Option<WalkTheDogStateMachine>,
+    // Set to replay a recorded run, otherwise every game picks its own
+    seed: Option<u64>,
+    run_events: Vec<RunEvent>,
+    controls: SharedControls,
+    settings: Settings,
+    // Paused by the player
+    paused: bool,
+    // Paused by the loop, e.g. while the tab is hidden. Resuming the loop
+    // leaves a pause the player asked for alone.
+    suspended: bool,
+    // Brings the pause text down from above the screen
+    pause_slide: Tween<f32>,
+    // Outlines hitboxes and shows the boy's state, to settle unfair deaths
+    debug: bool,
 }
 
 impl WalkTheDog {
     pub fn new() -> Self {
-        WalkTheDog { machine: None }
+        WalkTheDog {
+            machine: None,
+            seed: None,
+            run_events: vec![],
+            controls: SharedControls::default(),
+            settings: Settings::default(),
+            paused: false,
+            suspended: false,
+            pause_slide: pause_slide(),
+            debug: false,
+        }
+    }
+
+    // Plays the recorded run's seed with the controls it was recorded with,
+    // rather than whatever this player has bound
+    pub fn replaying(recording: &InputRecording) -> Result<Self> {
+        Ok(WalkTheDog {
+            seed: recording.seed(),
+            controls: SharedControls::recorded(recording.controls())?,
+            ..WalkTheDog::new()
+        })
+    }
+
+    // Stays in step with the game once it's running
+    pub fn controls(&self) -> SharedControls {
+        self.controls.clone()
+    }
+
+    pub fn set_screen_shake(&mut self, enabled: bool) -> Result<()> {
+        self.settings.screen_shake = enabled;
+        self.apply_settings();
+        self.settings.save(SETTINGS_STORAGE_KEY)
+    }
+
+    fn apply_settings(&mut self) {
+        if let Some(machine) = &mut self.machine {
+            machine
+                .walk_mut()
+                .camera
+                .set_shake_enabled(self.settings.screen_shake);
+        }
+    }
+
+    // Only from the pause screen, which is where the setting is shown
+    fn toggle_shake(&mut self, keystate: &KeyState) {
+        if self.controls.just_pressed(keystate, Action::ToggleShake) {
+            if let Err(err) = self.set_screen_shake(!self.settings.screen_shake) {
+                error!("Could not save settings {:#?}", err);
+            }
+        }
+    }
+
+    fn toggle_pause(&mut self, keystate: &KeyState) {
+        if self.controls.just_pressed(keystate, Action::Pause) {
+            if self.paused {
+                self.paused = false;
+            } else {
+                self.pause();
+            }
+        }
+    }
+
+    fn pause(&mut self) {
+        self.paused = true;
+        self.pause_slide = pause_slide();
+    }
+
+    fn toggle_debug(&mut self, keystate: &KeyState) {
+        if self.controls.just_pressed(keystate, Action::ToggleDebug) {
+            self.debug = !self.debug;
+        }
+    }
+
+    fn draw_paused(&self, renderer: &dyn Renderer) {
+        renderer.fill_rect(
+            &Rect::new(Point { x: 0, y: 0 }, WIDTH, HEIGHT),
+            "rgba(255, 255, 255, 0.5)",
+        );
+        let style = TextStyle::new(Font::new(engine::DEFAULT_FONT_FAMILY, 24))
+            .align(TextAlign::Center)
+            .baseline(TextBaseline::Middle);
+        let y = self.pause_slide.value().round() as i16;
+        let shake = if self.settings.screen_shake {
+            "Screen shake on - K to turn off"
+        } else {
+            "Screen shake off - K to turn on"
+        };
+        let setting_style = TextStyle {
+            font: Font::new(engine::DEFAULT_FONT_FAMILY, 12),
+            ..style.clone()
+        };
+        if let Err(err) = style
+            .draw(renderer, "Paused", &Point { x: WIDTH / 2, y })
+            .and_then(|_| {
+                setting_style.draw(
+                    renderer,
+                    shake,
+                    &Point {
+                        x: WIDTH / 2,
+                        y: y + 40,
+                    },
+                )
+            })
+        {
+            error!("Could not draw paused overlay {:#?}", err);
+        }
     }
 }
 
@@ -40,21 +366,40 @@ impl WalkTheDogStateMachine {
         WalkTheDogStateMachine::Ready(WalkTheDogState::new(walk))
     }
 
-    fn update(self, keystate: &KeyState) -> Self {
+    fn update(self, controls: &Controls) -> Self {
         match self {
-            WalkTheDogStateMachine::Ready(state) => state.update(keystate).into(),
-            WalkTheDogStateMachine::Walking(state) => state.update(keystate).into(),
+            WalkTheDogStateMachine::Ready(state) => state.update(controls).into(),
+            WalkTheDogStateMachine::Walking(state) => state.update(controls).into(),
             WalkTheDogStateMachine::GameOver(state) => state.update().into(),
         }
     }
 
-    fn draw(&self, renderer: &Renderer) {
+    fn draw<'a>(&'a self, draw_list: &mut DrawList<'a>, alpha: f32) {
         match self {
-            WalkTheDogStateMachine::Ready(state) => state.draw(renderer),
-            WalkTheDogStateMachine::Walking(state) => state.draw(renderer),
-            WalkTheDogStateMachine::GameOver(state) => state.draw(renderer),
+            WalkTheDogStateMachine::Ready(state) => state.draw(draw_list, alpha),
+            WalkTheDogStateMachine::Walking(state) => state.draw(draw_list, alpha),
+            WalkTheDogStateMachine::GameOver(state) => {
+                state.draw(draw_list, alpha);
+                state.draw_fade(draw_list);
+            }
         };
     }
+
+    fn walk(&self) -> &Walk {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => &state.walk,
+            WalkTheDogStateMachine::Walking(state) => &state.walk,
+            WalkTheDogStateMachine::GameOver(state) => &state.walk,
+        }
+    }
+
+    fn walk_mut(&mut self) -> &mut Walk {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => &mut state.walk,
+            WalkTheDogStateMachine::Walking(state) => &mut state.walk,
+            WalkTheDogStateMachine::GameOver(state) => &mut state.walk,
+        }
+    }
 }
 
 struct WalkTheDogState<T> {
@@ -63,8 +408,12 @@ struct WalkTheDogState<T> {
 }
 
 impl<T> WalkTheDogState<T> {
-    fn draw(&self, renderer: &Renderer) {
-        self.walk.draw(renderer);
+    fn draw<'a>(&'a self, draw_list: &mut DrawList<'a>, alpha: f32) {
+        self.walk.draw(draw_list, alpha);
+        let walk = &self.walk;
+        draw_list.submit(Layer::Hud, SCORE_Z, move |renderer| {
+            walk.draw_score(renderer)
+        });
     }
 }
 
@@ -72,7 +421,6 @@ struct Ready;
 
 impl WalkTheDogState<Ready> {
     fn new(walk: Walk) -> WalkTheDogState<Ready> {
-        browser::draw_ui("<p id='score'>0</>").unwrap();
         WalkTheDogState {
             _state: Ready,
             walk,
@@ -92,9 +440,9 @@ impl WalkTheDogState<Ready> {
         }
     }
 
-    fn update(mut self, keystate: &KeyState) -> ReadyEndState {
+    fn update(mut self, controls: &Controls) -> ReadyEndState {
         self.walk.boy.update();
-        if keystate.is_pressed("ArrowRight") {
+        if controls.is_active(Action::Run) {
             ReadyEndState::Complete(self.start_running())
         } else {
             ReadyEndState::Continue(self)
@@ -120,57 +468,54 @@ struct Walking;
 
 impl WalkTheDogState<Walking> {
     fn end_game(self) -> WalkTheDogState<GameOver> {
-        let receiver = browser::draw_ui("<button id='new_game'>New Game</button>")
-            .and_then(|_unit| browser::find_html_element_by_id("new_game"))
-            .map(engine::add_click_handler)
-            .unwrap();
-
         WalkTheDogState {
-            _state: GameOver {
-                new_game_event: receiver,
-            },
-
+            _state: GameOver::new(self.walk.seed()),
             walk: self.walk,
         }
     }
 
-    fn update(mut self, keystate: &KeyState) -> WalkingEndState {
-        if keystate.is_pressed("Space") {
+    fn update(mut self, controls: &Controls) -> WalkingEndState {
+        self.walk.camera.update_shake();
+        if self.walk.hit_stop.update() {
+            return WalkingEndState::Continue(self);
+        }
+
+        // Holding jump down shouldn't bounce the boy again as soon as he lands
+        if controls.just_pressed(Action::Jump) {
             self.walk.boy.jump();
         }
 
-        if keystate.is_pressed("ArrowDown") {
+        if controls.is_active(Action::Slide) {
             self.walk.boy.slide();
         }
 
         self.walk.boy.update();
+        self.walk.camera.follow(self.walk.boy.position());
 
-        let walking_speed = self.walk.velocity();
-        let [first_background, second_background] = &mut self.walk.backgrounds;
-        first_background.move_horizontally(walking_speed);
-        second_background.move_horizontally(walking_speed);
-
-        if first_background.right() < 0 {
-            first_background.set_x(second_background.right());
-        }
-        if second_background.right() < 0 {
-            second_background.set_x(first_background.right());
-        }
-
-        self.walk.obstacles.retain(|obstacle| obstacle.right() > 0);
+        let left = self.walk.camera.view().x();
+        self.walk
+            .obstacles
+            .retain(|obstacle| obstacle.right() > left);
 
-        self.walk.obstacles.iter_mut().for_each(|obstacle| {
-            obstacle.move_horizontally(walking_speed);
-            obstacle.check_intersection(&mut self.walk.boy);
+        let mut impact = Impact {
+            camera: &mut self.walk.camera,
+            hit_stop: &mut self.walk.hit_stop,
+        };
+        self.walk.obstacles.iter().for_each(|obstacle| {
+            obstacle.check_intersection(&mut self.walk.boy, &mut impact);
         });
 
-        if self.walk.timeline < TIMELINE_MINIMUM {
+        if self.walk.timeline < left + TIMELINE_MINIMUM {
             self.walk.generate_next_segment();
-        } else {
-            self.walk.timeline += walking_speed;
         }
 
+        self.walk.rebase();
+
         self.walk.score += 1;
+        if self.walk.score % SCORE_POPUP_INTERVAL == 0 {
+            self.walk.pop_up_score();
+        }
+        self.walk.update_popups();
 
         if self.walk.knocked_out() {
             WalkingEndState::Complete(self.end_game())
@@ -195,11 +540,16 @@ impl From<WalkingEndState> for WalkTheDogStateMachine {
 }
 
 struct GameOver {
+    // Clicks on the New Game button, which is shown once the fade finishes
     new_game_event: UnboundedReceiver<()>,
+    fade: Tween<f32>,
 }
 
 impl WalkTheDogState<GameOver> {
     fn update(mut self) -> GameOverEndState {
+        self.walk.camera.update_shake();
+        self.walk.boy.update_particles();
+        self._state.fade.update();
         if self._state.new_game_pressed() {
             GameOverEndState::Complete(self.new_game())
         } else {
@@ -214,7 +564,7 @@ impl WalkTheDogState<GameOver> {
 
         WalkTheDogState {
             _state: Ready,
-            walk: Walk::reset(self.walk),
+            walk: Walk::reset(self.walk, game_seed()),
         }
     }
 }
@@ -234,11 +584,102 @@ impl From<GameOverEndState> for WalkTheDogStateMachine {
 }
 
 impl GameOver {
+    fn new(seed: u64) -> Self {
+        let (clicks, new_game_event) = unbounded();
+        let fade = Tween::new(0.0, GAME_OVER_FADE_ALPHA, GAME_OVER_FADE_TICKS)
+            .easing(Easing::QuadOut)
+            .on_complete(move || {
+                if let Err(err) = draw_game_over_ui(seed, clicks) {
+                    error!("Could not show the game over screen {:#?}", err);
+                }
+            });
+
+        GameOver {
+            new_game_event,
+            fade,
+        }
+    }
+
     fn new_game_pressed(&mut self) -> bool {
         matches!(self.new_game_event.try_next(), Ok(Some(())))
     }
 }
 
+fn draw_game_over_ui(seed: u64, clicks: UnboundedSender<()>) -> Result<()> {
+    browser::draw_ui(&format!(
+        "<div><p id='seed'>Seed: {}</p><button id='new_game'>New Game</button></div>",
+        seed
+    ))
+    .and_then(|_unit| browser::find_html_element_by_id("new_game"))
+    .map(|button| engine::send_clicks(button, clicks))
+}
+
+impl WalkTheDogState<GameOver> {
+    fn draw_fade<'a>(&'a self, draw_list: &mut DrawList<'a>) {
+        let fade = &self._state.fade;
+        draw_list.submit(Layer::Hud, FADE_Z, move |renderer| {
+            renderer.fill_rect(
+                &Rect::new(Point { x: 0, y: 0 }, WIDTH, HEIGHT),
+                &format!("rgba(0, 0, 0, {})", fade.value()),
+            )
+        });
+    }
+}
+
+fn pause_slide() -> Tween<f32> {
+    Tween::new(-20.0, f32::from(HEIGHT / 2), PAUSE_SLIDE_TICKS).easing(Easing::BackOut)
+}
+
+// The score, growing in over the boy's head then drifting up and fading
+// out, each time it passes another SCORE_POPUP_INTERVAL
+struct ScorePopup {
+    text: String,
+    // Where in the world it was earned
+    origin: Point,
+    rise: Sequence<Point>,
+    scale: Tween<f32>,
+    alpha: Tween<f32>,
+}
+
+impl ScorePopup {
+    fn new(score: u16, origin: Point) -> Self {
+        let risen = Point { x: 0, y: -40 };
+        ScorePopup {
+            text: score.to_string(),
+            origin,
+            rise: Sequence::new(Tween::new(Point::default(), risen, 20).easing(Easing::QuadOut))
+                .then(Tween::new(risen, Point { x: 0, y: -50 }, 20)),
+            scale: Tween::new(0.5, 1.0, 12).easing(Easing::BackOut),
+            alpha: Tween::new(1.0, 0.0, 20).delay(20).easing(Easing::QuadIn),
+        }
+    }
+
+    fn update(&mut self) {
+        self.rise.update();
+        self.scale.update();
+        self.alpha.update();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.rise.is_finished() && self.alpha.is_finished()
+    }
+
+    fn draw(&self, renderer: &dyn Renderer) {
+        let size = (SCORE_POPUP_SIZE * self.scale.value()).round() as u16;
+        let style = TextStyle::new(Font::new(engine::DEFAULT_FONT_FAMILY, size))
+            .color(&format!("rgba(0, 0, 0, {})", self.alpha.value()))
+            .align(TextAlign::Center);
+        let offset = self.rise.value();
+        let location = Point {
+            x: self.origin.x + offset.x,
+            y: self.origin.y + offset.y,
+        };
+        if let Err(err) = style.draw(renderer, &self.text, &location) {
+            error!("Could not draw score popup {:#?}", err);
+        }
+    }
+}
+
 impl From<WalkTheDogState<Walking>> for WalkTheDogStateMachine {
     fn from(state: WalkTheDogState<Walking>) -> Self {
         WalkTheDogStateMachine::Walking(state)
@@ -257,9 +698,29 @@ impl From<WalkTheDogState<Ready>> for WalkTheDogStateMachine {
     }
 }
 
+// What an obstacle can shake up when it knocks the boy out
+pub struct Impact<'a> {
+    camera: &'a mut Camera,
+    hit_stop: &'a mut HitStop,
+}
+
+impl Impact<'_> {
+    // Shakes the screen and freezes the game for a moment, the first time
+    fn knock_out(&mut self, boy: &mut RedHatBoy) {
+        if boy.knock_out() {
+            self.camera.add_trauma(KNOCKOUT_TRAUMA);
+            self.hit_stop.freeze(KNOCKOUT_FREEZE_TICKS);
+        }
+    }
+}
+
 pub trait Obstacle {
-    fn check_intersection(&self, boy: &mut RedHatBoy);
-    fn draw(&self, renderer: &Renderer);
+    fn check_intersection(&self, boy: &mut RedHatBoy, impact: &mut Impact);
+    fn draw(&self, renderer: &dyn Renderer);
+    // Outlines whatever check_intersection tests the boy against
+    fn draw_debug(&self, renderer: &dyn Renderer);
+    // Obstacles stay where they were put in the world, except when the
+    // world's origin moves
     fn move_horizontally(&mut self, x: i16);
     fn right(&self) -> i16;
 }
@@ -309,7 +770,7 @@ impl Platform {
 }
 
 impl Obstacle for Platform {
-    fn check_intersection(&self, boy: &mut RedHatBoy) {
+    fn check_intersection(&self, boy: &mut RedHatBoy, impact: &mut Impact) {
         if let Some(box_to_land_on) = self
             .bounding_boxes()
             .iter()
@@ -318,34 +779,30 @@ impl Obstacle for Platform {
             if boy.velocity_y() > 0 && boy.pos_y() < self.position.y {
                 boy.land_on(box_to_land_on.y());
             } else {
-                boy.knock_out();
+                impact.knock_out(boy);
             }
         }
     }
 
-    fn draw(&self, renderer: &Renderer) {
+    fn draw(&self, renderer: &dyn Renderer) {
+        // Tiles sit side by side at their untrimmed widths
         let mut x = 0;
         self.sprites.iter().for_each(|sprite| {
-            self.sheet.draw(
-                renderer,
-                &Rect::new_from_x_y(
-                    sprite.frame.x,
-                    sprite.frame.y,
-                    sprite.frame.w,
-                    sprite.frame.h,
-                ),
-                // Just use position and the standard widths in the tileset
-                &Rect::new_from_x_y(
-                    self.position.x + x,
-                    self.position.y,
-                    sprite.frame.w,
-                    sprite.frame.h,
-                ),
-            );
-            x += sprite.frame.w;
+            let destination = sprite.destination(Point {
+                x: self.position.x + x,
+                y: self.position.y,
+            });
+            self.sheet.draw(renderer, sprite, &destination);
+            x += sprite.source_size.w;
         });
     }
 
+    fn draw_debug(&self, renderer: &dyn Renderer) {
+        self.bounding_boxes()
+            .iter()
+            .for_each(|bounding_box| renderer.draw_rect(bounding_box));
+    }
+
     fn move_horizontally(&mut self, x: i16) {
         self.position.x += x;
         self.bounding_boxes.iter_mut().for_each(|bounding_box| {
@@ -361,52 +818,201 @@ impl Obstacle for Platform {
     }
 }
 
+// What the boy kicks up, shared by every boy the walk resets to
+pub struct RedHatBoyEffects {
+    dust: Rc<ParticleEffect>,
+    trail: Rc<ParticleEffect>,
+    debris: Rc<ParticleEffect>,
+}
+
+impl RedHatBoyEffects {
+    fn new(debris_sheet: Rc<SpriteSheet>) -> Self {
+        let dirt = |size| ParticleLook::Color {
+            red: 190,
+            green: 160,
+            blue: 120,
+            size,
+        };
+        let cells = DEBRIS_SPRITES
+            .iter()
+            .filter_map(|name| debris_sheet.cell(name).cloned())
+            .collect();
+
+        RedHatBoyEffects {
+            dust: Rc::new(
+                ParticleEffect::new(dirt(4), Emission::Burst(10))
+                    .lifetime(20)
+                    .velocity((-2.0, 2.0), (-2.0, -0.5))
+                    .gravity(0.15)
+                    .fade(),
+            ),
+            trail: Rc::new(
+                ParticleEffect::new(dirt(3), Emission::Continuous(0.5))
+                    .lifetime(15)
+                    .velocity((-1.5, -0.5), (-1.0, 0.0))
+                    .gravity(0.05)
+                    .fade(),
+            ),
+            debris: Rc::new(
+                ParticleEffect::new(
+                    ParticleLook::Sprites {
+                        sheet: debris_sheet,
+                        cells,
+                        scale: 0.2,
+                    },
+                    Emission::Burst(8),
+                )
+                .lifetime(45)
+                .velocity((-3.0, 3.0), (-6.0, -2.0))
+                .gravity(0.4)
+                .spin((-0.3, 0.3))
+                .fade(),
+            ),
+        }
+    }
+}
+
 pub struct RedHatBoy {
     state_machine: RedHatBoyStateMachine,
-    sprite_sheet: Sheet,
-    image: HtmlImageElement,
+    sprite_sheet: Rc<SpriteSheet>,
+    previous_position: Point,
+    effects: Rc<RedHatBoyEffects>,
+    particles: ParticleSystem,
+    trail: Option<EmitterId>,
 }
 
 impl RedHatBoy {
-    fn new(sprite_sheet: Sheet, image: HtmlImageElement, audio: Audio, sound: Sound) -> Self {
+    fn new(
+        sprite_sheet: Rc<SpriteSheet>,
+        audio: Audio,
+        sound: Sound,
+        effects: Rc<RedHatBoyEffects>,
+    ) -> Result<Self> {
+        let clips = Rc::new(RedHatBoyClips::from_sheet(&sprite_sheet)?);
+        Ok(RedHatBoy::with_clips(
+            sprite_sheet,
+            audio,
+            sound,
+            clips,
+            effects,
+        ))
+    }
+
+    fn with_clips(
+        sprite_sheet: Rc<SpriteSheet>,
+        audio: Audio,
+        sound: Sound,
+        clips: Rc<RedHatBoyClips>,
+        effects: Rc<RedHatBoyEffects>,
+    ) -> Self {
+        let state_machine = RedHatBoyStateMachine::Idle(RedHatBoyState::new(audio, sound, clips));
+        let previous_position = state_machine.context().position;
         RedHatBoy {
-            state_machine: RedHatBoyStateMachine::Idle(RedHatBoyState::new(audio, sound)),
+            state_machine,
             sprite_sheet,
-            image,
+            previous_position,
+            effects,
+            particles: ParticleSystem::new(),
+            trail: None,
         }
     }
 
     fn reset(boy: Self) -> Self {
-        RedHatBoy::new(
+        RedHatBoy::with_clips(
             boy.sprite_sheet,
-            boy.image,
             boy.state_machine.context().audio.clone(),
             boy.state_machine.context().jump_sound.clone(),
+            boy.state_machine.context().clips.clone(),
+            boy.effects,
         )
     }
 
     fn run_right(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Run);
+        self.transition(Event::Run);
     }
 
     fn slide(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Slide);
+        self.transition(Event::Slide);
     }
 
     fn jump(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Jump);
+        self.transition(Event::Jump);
     }
 
-    fn knock_out(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::KnockOut);
+    // True if this knocked him down, false if he was down already
+    fn knock_out(&mut self) -> bool {
+        let down = self.is_down();
+        self.transition(Event::KnockOut);
+        !down && self.is_down()
+    }
+
+    fn is_down(&self) -> bool {
+        matches!(
+            self.state_machine,
+            RedHatBoyStateMachine::Falling(_) | RedHatBoyStateMachine::KnockedOut(_)
+        )
     }
 
     fn land_on(&mut self, position: i16) {
-        self.state_machine = self.state_machine.clone().transition(Event::Land(position));
+        self.transition(Event::Land(position));
     }
 
     fn update(&mut self) {
-        self.state_machine = self.state_machine.clone().update();
+        self.previous_position = self.state_machine.context().position;
+        self.transition(Event::Update);
+        self.update_particles();
+    }
+
+    // Also called once he's stopped, so what he kicked up settles
+    fn update_particles(&mut self) {
+        if let Some(trail) = self.trail {
+            self.particles.move_emitter(trail, self.feet());
+        }
+        self.particles.update();
+    }
+
+    fn transition(&mut self, event: Event) {
+        let next = self.state_machine.clone().transition(event);
+        let previous = std::mem::replace(&mut self.state_machine, next);
+        self.spawn_particles(&previous);
+    }
+
+    // Dust when he lands, a trail while he slides and debris when he hits
+    // something
+    fn spawn_particles(&mut self, previous: &RedHatBoyStateMachine) {
+        use RedHatBoyStateMachine::{Falling, Jumping, Running, Sliding};
+
+        if matches!((previous, &self.state_machine), (Jumping(_), Running(_))) {
+            self.particles.emit(&self.effects.dust, self.feet());
+        }
+        if !matches!(previous, Falling(_)) && matches!(self.state_machine, Falling(_)) {
+            let bounding_box = self.bounding_box();
+            let centre = Point {
+                x: bounding_box.x() + bounding_box.width / 2,
+                y: bounding_box.y() + bounding_box.height / 2,
+            };
+            self.particles.emit(&self.effects.debris, centre);
+        }
+
+        let sliding = matches!(self.state_machine, Sliding(_));
+        match self.trail {
+            None if sliding => {
+                self.trail = Some(self.particles.emit(&self.effects.trail, self.feet()));
+            }
+            Some(trail) if !sliding => {
+                self.particles.stop(trail);
+                self.trail = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn feet(&self) -> Point {
+        let bounding_box = self.bounding_box();
+        Point {
+            x: bounding_box.x() + bounding_box.width / 2,
+            y: bounding_box.bottom(),
+        }
     }
 
     fn knocked_out(&self) -> bool {
@@ -421,20 +1027,20 @@ impl RedHatBoy {
         self.state_machine.context().velocity.y
     }
 
-    fn walking_speed(&self) -> i16 {
-        self.state_machine.context().velocity.x
+    fn position(&self) -> Point {
+        self.state_machine.context().position
     }
 
-    fn frame_name(&self) -> String {
-        format!(
-            "{} ({}).png",
-            self.state_machine.frame_name(),
-            (self.state_machine.context().frame / 3) + 1
-        )
+    fn shift(&mut self, x: i16) {
+        self.state_machine.shift(x);
+        self.previous_position.x += x;
+        self.particles.shift(Point { x, y: 0 });
     }
 
     fn current_sprite(&self) -> Option<&Cell> {
-        self.sprite_sheet.frames.get(&self.frame_name())
+        self.state_machine
+            .clip()
+            .frame_at(self.state_machine.context().frame)
     }
 
     fn bounding_box(&self) -> Rect {
@@ -450,29 +1056,46 @@ impl RedHatBoy {
     }
 
     fn destination_box(&self) -> Rect {
+        let context = self.state_machine.context();
+        self.state_machine
+            .clip()
+            .destination(context.frame, context.position)
+            .expect("Cell not found")
+    }
+
+    fn draw(&self, renderer: &dyn Renderer, alpha: f32) {
         let sprite = self.current_sprite().expect("Cell not found");
+        let position = self.state_machine.context().position;
+        let blended = Point {
+            x: engine::lerp(self.previous_position.x, position.x, alpha),
+            y: engine::lerp(self.previous_position.y, position.y, alpha),
+        };
 
-        Rect::new_from_x_y(
-            self.state_machine.context().position.x + sprite.sprite_source_size.x,
-            self.state_machine.context().position.y + sprite.sprite_source_size.y,
-            sprite.frame.w,
-            sprite.frame.h,
-        )
+        let destination = self
+            .state_machine
+            .clip()
+            .destination(self.state_machine.context().frame, blended)
+            .expect("Cell not found");
+        self.sprite_sheet.draw(renderer, sprite, &destination);
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        let sprite = self.current_sprite().expect("Cell not found");
+    fn draw_particles(&self, renderer: &dyn Renderer) {
+        self.particles.draw(renderer);
+    }
+}
 
-        renderer.draw_image(
-            &self.image,
-            &Rect::new_from_x_y(
-                sprite.frame.x,
-                sprite.frame.y,
-                sprite.frame.w,
-                sprite.frame.h,
-            ),
-            &self.destination_box(),
-        );
+impl RedHatBoy {
+    fn draw_debug(&self, renderer: &dyn Renderer) {
+        renderer.draw_rect(&self.bounding_box());
+    }
+
+    fn debug_lines(&self) -> [String; 3] {
+        let context = self.state_machine.context();
+        [
+            self.state_machine.state_name().to_string(),
+            format!("Velocity {}, {}", context.velocity.x, context.velocity.y),
+            format!("Particles {}", self.particles.live_particles()),
+        ]
     }
 }
 
@@ -522,14 +1145,25 @@ impl RedHatBoyStateMachine {
         }
     }
 
-    fn frame_name(&self) -> &str {
+    fn clip(&self) -> &Clip {
         match self {
-            RedHatBoyStateMachine::Idle(state) => state.frame_name(),
-            RedHatBoyStateMachine::Running(state) => state.frame_name(),
-            RedHatBoyStateMachine::Jumping(state) => state.frame_name(),
-            RedHatBoyStateMachine::Sliding(state) => state.frame_name(),
-            RedHatBoyStateMachine::Falling(state) => state.frame_name(),
-            RedHatBoyStateMachine::KnockedOut(state) => state.frame_name(),
+            RedHatBoyStateMachine::Idle(state) => state.clip(),
+            RedHatBoyStateMachine::Running(state) => state.clip(),
+            RedHatBoyStateMachine::Jumping(state) => state.clip(),
+            RedHatBoyStateMachine::Sliding(state) => state.clip(),
+            RedHatBoyStateMachine::Falling(state) => state.clip(),
+            RedHatBoyStateMachine::KnockedOut(state) => state.clip(),
+        }
+    }
+
+    fn state_name(&self) -> &'static str {
+        match self {
+            RedHatBoyStateMachine::Idle(_) => "Idle",
+            RedHatBoyStateMachine::Running(_) => "Running",
+            RedHatBoyStateMachine::Jumping(_) => "Jumping",
+            RedHatBoyStateMachine::Sliding(_) => "Sliding",
+            RedHatBoyStateMachine::Falling(_) => "Falling",
+            RedHatBoyStateMachine::KnockedOut(_) => "KnockedOut",
         }
     }
 
@@ -548,8 +1182,15 @@ impl RedHatBoyStateMachine {
         matches!(self, RedHatBoyStateMachine::KnockedOut(_))
     }
 
-    fn update(self) -> Self {
-        self.transition(Event::Update)
+    fn shift(&mut self, x: i16) {
+        match self {
+            RedHatBoyStateMachine::Idle(state) => state.shift(x),
+            RedHatBoyStateMachine::Running(state) => state.shift(x),
+            RedHatBoyStateMachine::Jumping(state) => state.shift(x),
+            RedHatBoyStateMachine::Sliding(state) => state.shift(x),
+            RedHatBoyStateMachine::Falling(state) => state.shift(x),
+            RedHatBoyStateMachine::KnockedOut(state) => state.shift(x),
+        }
     }
 }
 
@@ -618,22 +1259,19 @@ impl From<FallingEndState> for RedHatBoyStateMachine {
 
 mod red_hat_boy_states {
     use super::{Audio, Sound, HEIGHT};
-    use crate::engine::Point;
+    use crate::engine::{Clip, Point, SpriteSheet};
+    use anyhow::{anyhow, Result};
+    use std::rc::Rc;
 
     const FLOOR: i16 = 479;
     const PLAYER_HEIGHT: i16 = HEIGHT - FLOOR;
     const RUNNING_SPEED: i16 = 4;
     const STARTING_POINT: i16 = -20;
-    const IDLE_FRAMES: u8 = 29;
-    const RUNNING_FRAMES: u8 = 23;
-    const JUMPING_FRAMES: u8 = 35;
-    const SLIDING_FRAMES: u8 = 14;
-    const FALLING_FRAMES: u8 = 29;
-    const IDLE_FRAME_NAME: &str = "Idle";
-    const RUN_FRAME_NAME: &str = "Run";
-    const SLIDING_FRAME_NAME: &str = "Slide";
-    const JUMPING_FRAME_NAME: &str = "Jump";
-    const FALLING_FRAME_NAME: &str = "Dead";
+    const IDLE_CLIP: &str = "Idle";
+    const RUN_CLIP: &str = "Run";
+    const SLIDING_CLIP: &str = "Slide";
+    const JUMPING_CLIP: &str = "Jump";
+    const FALLING_CLIP: &str = "Dead";
     const JUMP_SPEED: i16 = -25;
     const GRAVITY: i16 = 1;
     const TERMINAL_VELOCITY: i16 = 20;
@@ -649,8 +1287,39 @@ mod red_hat_boy_states {
             &self.context
         }
 
-        fn update_context(&mut self, frames: u8) {
-            self.context = self.context.clone().update(frames);
+        fn update_context(&mut self) {
+            self.context = self.context.clone().update();
+        }
+
+        pub fn shift(&mut self, x: i16) {
+            self.context.position.x += x;
+        }
+    }
+
+    // The boy's animations, looked up once so each state can borrow its own
+    pub struct RedHatBoyClips {
+        idle: Clip,
+        run: Clip,
+        slide: Clip,
+        jump: Clip,
+        dead: Clip,
+    }
+
+    impl RedHatBoyClips {
+        pub fn from_sheet(sheet: &SpriteSheet) -> Result<Self> {
+            let clip = |name| {
+                sheet
+                    .clip(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("No {} animation in the sprite sheet", name))
+            };
+            Ok(RedHatBoyClips {
+                idle: clip(IDLE_CLIP)?,
+                run: clip(RUN_CLIP)?,
+                slide: clip(SLIDING_CLIP)?,
+                jump: clip(JUMPING_CLIP)?,
+                dead: clip(FALLING_CLIP)?,
+            })
         }
     }
 
@@ -658,7 +1327,7 @@ mod red_hat_boy_states {
     pub struct Idle;
 
     impl RedHatBoyState<Idle> {
-        pub fn new(audio: Audio, jump_sound: Sound) -> Self {
+        pub fn new(audio: Audio, jump_sound: Sound, clips: Rc<RedHatBoyClips>) -> Self {
             RedHatBoyState {
                 context: RedHatBoyContext {
                     frame: 0,
@@ -669,17 +1338,18 @@ mod red_hat_boy_states {
                     velocity: Point { x: 0, y: 0 },
                     audio,
                     jump_sound,
+                    clips,
                 },
                 _state: Idle {},
             }
         }
 
-        pub fn frame_name(&self) -> &str {
-            IDLE_FRAME_NAME
+        pub fn clip(&self) -> &Clip {
+            &self.context.clips.idle
         }
 
         pub fn update(mut self) -> RedHatBoyState<Idle> {
-            self.update_context(IDLE_FRAMES);
+            self.update_context();
             self
         }
 
@@ -695,12 +1365,12 @@ mod red_hat_boy_states {
     pub struct Running;
 
     impl RedHatBoyState<Running> {
-        pub fn frame_name(&self) -> &str {
-            RUN_FRAME_NAME
+        pub fn clip(&self) -> &Clip {
+            &self.context.clips.run
         }
 
         pub fn update(mut self) -> RedHatBoyState<Running> {
-            self.update_context(RUNNING_FRAMES);
+            self.update_context();
             self
         }
 
@@ -746,8 +1416,8 @@ mod red_hat_boy_states {
     }
 
     impl RedHatBoyState<Jumping> {
-        pub fn frame_name(&self) -> &str {
-            JUMPING_FRAME_NAME
+        pub fn clip(&self) -> &Clip {
+            &self.context.clips.jump
         }
 
         pub fn knock_out(self) -> RedHatBoyState<Falling> {
@@ -758,7 +1428,7 @@ mod red_hat_boy_states {
         }
 
         pub fn update(mut self) -> JumpingEndState {
-            self.update_context(JUMPING_FRAMES);
+            self.update_context();
 
             if self.context.position.y >= FLOOR {
                 JumpingEndState::Landing(self.land_on(HEIGHT))
@@ -784,8 +1454,8 @@ mod red_hat_boy_states {
     }
 
     impl RedHatBoyState<Sliding> {
-        pub fn frame_name(&self) -> &str {
-            SLIDING_FRAME_NAME
+        pub fn clip(&self) -> &Clip {
+            &self.context.clips.slide
         }
 
         pub fn stand(self) -> RedHatBoyState<Running> {
@@ -803,9 +1473,9 @@ mod red_hat_boy_states {
         }
 
         pub fn update(mut self) -> SlidingEndState {
-            self.update_context(SLIDING_FRAMES);
+            self.update_context();
 
-            if self.context.frame >= SLIDING_FRAMES {
+            if self.clip().is_finished(self.context.frame) {
                 SlidingEndState::Running(self.stand())
             } else {
                 SlidingEndState::Sliding(self)
@@ -824,8 +1494,8 @@ mod red_hat_boy_states {
     pub struct Falling;
 
     impl RedHatBoyState<Falling> {
-        pub fn frame_name(&self) -> &str {
-            FALLING_FRAME_NAME
+        pub fn clip(&self) -> &Clip {
+            &self.context.clips.dead
         }
 
         pub fn knock_out(self) -> RedHatBoyState<KnockedOut> {
@@ -836,8 +1506,8 @@ mod red_hat_boy_states {
         }
 
         pub fn update(mut self) -> FallingEndState {
-            self.update_context(FALLING_FRAMES);
-            if self.context.frame >= FALLING_FRAMES {
+            self.update_context();
+            if self.clip().is_finished(self.context.frame) {
                 FallingEndState::KnockedOut(self.knock_out())
             } else {
                 FallingEndState::Falling(self)
@@ -854,32 +1524,31 @@ mod red_hat_boy_states {
     pub struct KnockedOut;
 
     impl RedHatBoyState<KnockedOut> {
-        pub fn frame_name(&self) -> &str {
-            FALLING_FRAME_NAME
+        pub fn clip(&self) -> &Clip {
+            &self.context.clips.dead
         }
     }
 
     #[derive(Clone)]
     pub struct RedHatBoyContext {
-        pub frame: u8,
+        // Ticks since the current animation started
+        pub frame: u32,
         pub position: Point,
         pub velocity: Point,
         pub audio: Audio,
         pub jump_sound: Sound,
+        pub clips: Rc<RedHatBoyClips>,
     }
 
     impl RedHatBoyContext {
-        pub fn update(mut self, frame_count: u8) -> Self {
+        pub fn update(mut self) -> Self {
             if self.velocity.y < TERMINAL_VELOCITY {
                 self.velocity.y += GRAVITY;
             }
 
-            if self.frame < frame_count {
-                self.frame += 1;
-            } else {
-                self.frame = 0;
-            }
+            self.frame = self.frame.saturating_add(1);
 
+            self.position.x += self.velocity.x;
             self.position.y += self.velocity.y;
 
             if self.position.y > FLOOR {
@@ -926,13 +1595,20 @@ mod red_hat_boy_states {
 }
 
 pub struct Walk {
+    camera: Camera,
+    hit_stop: HitStop,
     obstacle_sheet: Rc<SpriteSheet>,
     stone: HtmlImageElement,
     boy: RedHatBoy,
-    backgrounds: [Image; 2],
+    background: Parallax,
     obstacles: Vec<Box<dyn Obstacle>>,
+    popups: Vec<ScorePopup>,
     timeline: i16,
     score: u16,
+    seed: u64,
+    // Unlike StdRng, ChaCha8 gives the same numbers for a seed in every
+    // release of rand, so a seed always builds the same level
+    rng: ChaCha8Rng,
 }
 
 impl Walk {
@@ -940,62 +1616,200 @@ impl Walk {
         self.boy.knocked_out()
     }
 
-    fn reset(walk: Self) -> Self {
+    fn reset(walk: Self, seed: u64) -> Self {
         let starting_obstacles =
             stone_and_platform(walk.stone.clone(), walk.obstacle_sheet.clone(), 0);
         let timeline = rightmost(&starting_obstacles);
 
+        let mut background = walk.background;
+        background.reset();
+
+        let mut camera = walk_camera();
+        camera.set_shake_enabled(walk.camera.is_shake_enabled());
+
         Walk {
+            camera,
+            hit_stop: HitStop::default(),
             boy: RedHatBoy::reset(walk.boy),
-            backgrounds: walk.backgrounds,
+            background,
             obstacles: starting_obstacles,
+            popups: vec![],
             obstacle_sheet: walk.obstacle_sheet,
             stone: walk.stone,
             score: 0,
             timeline,
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
         }
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        self.backgrounds.iter().for_each(|background| {
-            background.draw(renderer);
-        });
-        self.boy.draw(renderer);
+    fn seed(&self) -> u64 {
+        self.seed
+    }
 
-        self.obstacles.iter().for_each(|obstacle| {
-            obstacle.draw(renderer);
+    fn draw<'a>(&'a self, draw_list: &mut DrawList<'a>, alpha: f32) {
+        // Nothing moves during a hit-stop, so blending would just wobble
+        let alpha = if self.hit_stop.is_frozen() {
+            1.0
+        } else {
+            alpha
+        };
+        let camera = &self.camera;
+
+        draw_list.submit(Layer::Background, 0, move |renderer| {
+            self.background.draw(renderer, &camera.view_at(alpha))
         });
-        browser::find_html_element_by_id("score")
-            .map(|element| element.set_inner_html(&format!("Score: {}", self.score)))
-            .unwrap();
+        for obstacle in &self.obstacles {
+            draw_list.submit(Layer::World, OBSTACLE_Z, move |renderer| {
+                obstacle.draw(&camera.renderer(renderer, alpha))
+            });
+        }
+        draw_list.submit(Layer::World, BOY_Z, move |renderer| {
+            self.boy.draw(&camera.renderer(renderer, alpha), alpha)
+        });
+        draw_list.submit(Layer::Effects, 0, move |renderer| {
+            self.boy.draw_particles(&camera.renderer(renderer, alpha))
+        });
+        for popup in &self.popups {
+            draw_list.submit(Layer::Effects, POPUP_Z, move |renderer| {
+                popup.draw(&camera.renderer(renderer, alpha))
+            });
+        }
+    }
+
+    fn pop_up_score(&mut self) {
+        let bounding_box = self.boy.bounding_box();
+        let origin = Point {
+            x: bounding_box.x() + bounding_box.width / 2,
+            y: bounding_box.y(),
+        };
+        self.popups.push(ScorePopup::new(self.score, origin));
     }
 
-    fn velocity(&self) -> i16 {
-        -self.boy.walking_speed()
+    fn update_popups(&mut self) {
+        self.popups.iter_mut().for_each(ScorePopup::update);
+        self.popups.retain(|popup| !popup.is_finished());
     }
 
-    fn generate_next_segment(&mut self) {
-        let mut rng = thread_rng();
-        let next_segment = rng.gen_range(0..2);
+    fn draw_score(&self, renderer: &dyn Renderer) {
+        let style = TextStyle::new(Font::new(engine::DEFAULT_FONT_FAMILY, 16).bold())
+            .align(TextAlign::Right)
+            .baseline(TextBaseline::Top);
+        let location = Point {
+            x: WIDTH - 10,
+            y: 10,
+        };
+        if let Err(err) = style.draw(renderer, &format!("Score: {}", self.score), &location) {
+            error!("Could not draw the score {:#?}", err);
+        }
+    }
+
+    // Points are i16, so rather than let world coordinates grow until they
+    // overflow, everything is moved back to the origin once the camera has
+    // gone far enough.
+    fn rebase(&mut self) {
+        let distance = self.camera.view().x();
+        if distance < REBASE_DISTANCE {
+            return;
+        }
+
+        self.boy.shift(-distance);
+        self.background.shift(Point { x: -distance, y: 0 });
+        self.popups
+            .iter_mut()
+            .for_each(|popup| popup.origin.x -= distance);
+        self.obstacles
+            .iter_mut()
+            .for_each(|obstacle| obstacle.move_horizontally(-distance));
+        self.timeline -= distance;
+        self.camera.shift(Point { x: -distance, y: 0 });
+    }
+
+    // Drawn where the last update left everything rather than blended like
+    // the sprites, since that is what the collisions were checked against.
+    fn draw_debug(&self, renderer: &dyn Renderer) {
+        let world = self.camera.renderer(renderer, 1.0);
+        self.obstacles
+            .iter()
+            .for_each(|obstacle| obstacle.draw_debug(&world));
+        self.boy.draw_debug(&world);
+        world.draw_rect(&Rect::new_from_x_y(self.timeline, 0, 1, HEIGHT));
+
+        let [state, velocity, particles] = self.boy.debug_lines();
+        let lines = [
+            state,
+            velocity,
+            particles,
+            format!("Timeline {}", self.timeline),
+        ];
+        for (line, text) in lines.iter().enumerate() {
+            let location = Point {
+                x: 10,
+                y: 30 + 25 * line as i16,
+            };
+            if let Err(err) = renderer.draw_text(text, &location) {
+                error!("Could not draw debug text {:#?}", err);
+            }
+        }
+    }
 
-        let mut next_obstacles = match next_segment {
-            0 => stone_and_platform(
+    fn generate_next_segment(&mut self) {
+        let mut next_obstacles = match Segment::random(&mut self.rng) {
+            Segment::StoneAndPlatform => stone_and_platform(
                 self.stone.clone(),
                 self.obstacle_sheet.clone(),
                 self.timeline + OBSTACLE_BUFFER,
             ),
-            1 => platform_and_stone(
+            Segment::PlatformAndStone => platform_and_stone(
                 self.stone.clone(),
                 self.obstacle_sheet.clone(),
                 self.timeline + OBSTACLE_BUFFER,
             ),
-            _ => vec![],
         };
         self.timeline = rightmost(&next_obstacles);
         self.obstacles.append(&mut next_obstacles);
     }
 }
 
+// The boy stays at the left edge of the screen, where he starts, and the
+// view never scrolls back past the start or up and down.
+fn walk_camera() -> Camera {
+    Camera::new(WIDTH, HEIGHT)
+        .with_dead_zone(Rect::new_from_x_y(-20, 0, 0, HEIGHT))
+        .with_bounds(Rect::new_from_x_y(0, 0, i16::MAX, HEIGHT))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Segment {
+    StoneAndPlatform,
+    PlatformAndStone,
+}
+
+impl Segment {
+    fn random(rng: &mut ChaCha8Rng) -> Self {
+        match rng.gen_range(0..2) {
+            0 => Segment::StoneAndPlatform,
+            _ => Segment::PlatformAndStone,
+        }
+    }
+}
+
+// A seed in the page URL (?seed=1234) pins every game to the same segments,
+// otherwise each game gets a fresh one.
+fn game_seed() -> u64 {
+    match browser::query_param("seed") {
+        Ok(Some(seed)) => seed.parse().unwrap_or_else(|err| {
+            error!("Could not parse seed {}: {:#?}", seed, err);
+            thread_rng().gen()
+        }),
+        Ok(None) => thread_rng().gen(),
+        Err(err) => {
+            error!("Could not read seed from the page {:#?}", err);
+            thread_rng().gen()
+        }
+    }
+}
+
 pub struct Barrier {
     image: Image,
 }
@@ -1007,16 +1821,20 @@ impl Barrier {
 }
 
 impl Obstacle for Barrier {
-    fn check_intersection(&self, boy: &mut RedHatBoy) {
+    fn check_intersection(&self, boy: &mut RedHatBoy, impact: &mut Impact) {
         if boy.bounding_box().intersects(self.image.bounding_box()) {
-            boy.knock_out()
+            impact.knock_out(boy);
         }
     }
 
-    fn draw(&self, renderer: &Renderer) {
+    fn draw(&self, renderer: &dyn Renderer) {
         self.image.draw(renderer);
     }
 
+    fn draw_debug(&self, renderer: &dyn Renderer) {
+        renderer.draw_rect(self.image.bounding_box());
+    }
+
     fn move_horizontally(&mut self, x: i16) {
         self.image.move_horizontally(x);
     }
@@ -1028,74 +1846,147 @@ impl Obstacle for Barrier {
 
 #[async_trait(?Send)]
 impl Game for WalkTheDog {
-    async fn initialize(&self) -> Result<Box<dyn Game>> {
+    async fn initialize(&self, loader: &AssetLoader) -> Result<Box<dyn Game>> {
         match self.machine {
             None => {
-                let sheet = browser::fetch_json("rhb.json")
-                    .await?
-                    .into_serde::<Sheet>()?;
-                let background = engine::load_image("BG.png").await?;
-                let stone = engine::load_image("Stone.png").await?;
-
-                let tiles = browser::fetch_json("tiles.json").await?;
+                let audio = Audio::new()?;
+                let assets = loader.load(ASSET_MANIFEST, &audio).await?;
 
-                let sprite_sheet = Rc::new(SpriteSheet::new(
-                    tiles.into_serde::<Sheet>()?,
-                    engine::load_image("tiles.png").await?,
-                ));
+                let background = assets.parallax(&BACKGROUND)?;
+                let stone = assets.image(&STONE)?;
+                let sprite_sheet = assets.sprite_sheet(&TILES)?;
 
-                let audio = Audio::new()?;
-                let sound = audio.load_sound("SFX_Jump_23.mp3").await?;
-                let background_music = audio.load_sound("background_song.mp3").await?;
+                let background_music = assets.sound(&MUSIC)?;
                 audio.play_looping_sound(&background_music)?;
 
-                let rhb = RedHatBoy::new(sheet, engine::load_image("rhb.png").await?, audio, sound);
+                let rhb = RedHatBoy::new(
+                    assets.sprite_sheet(&RHB)?,
+                    audio,
+                    assets.sound(&JUMP_SOUND)?,
+                    Rc::new(RedHatBoyEffects::new(sprite_sheet.clone())),
+                )?;
 
-                let background_width = background.width() as i16;
                 let starting_obstacles = stone_and_platform(stone.clone(), sprite_sheet.clone(), 0);
                 let timeline = rightmost(&starting_obstacles);
+                let seed = self.seed.unwrap_or_else(game_seed);
 
                 let machine = WalkTheDogStateMachine::new(Walk {
+                    camera: walk_camera(),
+                    hit_stop: HitStop::default(),
                     boy: rhb,
-                    backgrounds: [
-                        Image::new(background.clone(), Point { x: 0, y: 0 }),
-                        Image::new(
-                            background,
-                            Point {
-                                x: background_width,
-                                y: 0,
-                            },
-                        ),
-                    ],
+                    background,
                     obstacles: starting_obstacles,
+                    popups: vec![],
                     obstacle_sheet: sprite_sheet,
                     score: 0,
                     stone,
                     timeline,
+                    seed,
+                    rng: ChaCha8Rng::seed_from_u64(seed),
                 });
 
-                Ok(Box::new(WalkTheDog {
+                self.controls.load();
+                let mut game = WalkTheDog {
                     machine: Some(machine),
-                }))
+                    seed: Some(seed),
+                    run_events: vec![RunEvent::Started {
+                        seed,
+                        controls: self.controls.to_recording(),
+                    }],
+                    controls: self.controls.clone(),
+                    settings: load_settings(),
+                    paused: false,
+                    suspended: false,
+                    pause_slide: pause_slide(),
+                    debug: false,
+                };
+                game.apply_settings();
+                Ok(Box::new(game))
             }
             Some(_) => Err(anyhow!("Error: Game is already initialized!")),
         }
     }
 
     fn update(&mut self, keystate: &KeyState) {
+        self.toggle_pause(keystate);
+        self.toggle_debug(keystate);
+        if self.paused {
+            self.toggle_shake(keystate);
+            self.pause_slide.update();
+            return;
+        }
+
         if let Some(machine) = self.machine.take() {
-            self.machine.replace(machine.update(keystate));
+            let was_walking = matches!(machine, WalkTheDogStateMachine::Walking(_));
+            let was_over = matches!(machine, WalkTheDogStateMachine::GameOver(_));
+            let machine = self
+                .controls
+                .with_state(keystate, |controls| machine.update(controls));
+            match &machine {
+                WalkTheDogStateMachine::GameOver(_) if was_walking => {
+                    self.run_events.push(RunEvent::Ended)
+                }
+                WalkTheDogStateMachine::Ready(state) if was_over => {
+                    self.run_events.push(RunEvent::Started {
+                        seed: state.walk.seed(),
+                        controls: self.controls.to_recording(),
+                    })
+                }
+                _ => {}
+            }
+            self.machine.replace(machine);
         }
         assert!(self.machine.is_some());
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        renderer.clear(&Rect::new(Point { x: 0, y: 0 }, 600, HEIGHT));
+    fn draw<'a>(&'a self, draw_list: &mut DrawList<'a>, alpha: f32) {
+        draw_list.submit(Layer::Background, i16::MIN, |renderer| {
+            renderer.clear(&Rect::new(Point { x: 0, y: 0 }, WIDTH, HEIGHT))
+        });
 
         if let Some(machine) = &self.machine {
-            machine.draw(renderer);
+            machine.draw(draw_list, alpha);
+            if self.debug {
+                let walk = machine.walk();
+                draw_list.submit(Layer::Hud, DEBUG_Z, move |renderer| {
+                    walk.draw_debug(renderer)
+                });
+            }
+        }
+
+        if self.paused || self.suspended {
+            draw_list.submit(Layer::Hud, PAUSED_Z, move |renderer| {
+                self.draw_paused(renderer)
+            });
+        }
+    }
+
+    // Nothing updates while the loop is paused, so the text can't slide in
+    fn on_pause(&mut self) {
+        self.suspended = true;
+        self.pause_slide.finish();
+    }
+
+    fn on_resume(&mut self) {
+        self.suspended = false;
+    }
+
+    fn take_run_events(&mut self) -> Vec<RunEvent> {
+        std::mem::take(&mut self.run_events)
+    }
+
+    fn record_metrics(&self, metrics: &mut Metrics) {
+        if let Some(machine) = &self.machine {
+            metrics.set_counter("Obstacles", machine.walk().obstacles.len() as i64);
         }
     }
+
+    fn virtual_buttons(&self) -> Vec<VirtualButton> {
+        vec![
+            VirtualButton::new(Rect::new_from_x_y(10, 510, 120, 80), VIRTUAL_SLIDE, "Slide"),
+            VirtualButton::new(Rect::new_from_x_y(470, 510, 120, 80), VIRTUAL_JUMP, "Jump"),
+        ]
+    }
 }
 
 fn rightmost(obstacle_list: &[Box<dyn Obstacle>]) -> i16 {
@@ -1109,8 +2000,12 @@ fn rightmost(obstacle_list: &[Box<dyn Obstacle>]) -> i16 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::engine::{
+        ClipDefinition, DrawCommand, RecordingRenderer, Sheet, SheetRect, SheetSize,
+    };
     use futures::channel::mpsc::unbounded;
     use std::collections::HashMap;
+    use wasm_bindgen::{JsCast, JsValue};
     use web_sys::{AudioBuffer, AudioBufferOptions};
 
     use wasm_bindgen_test::wasm_bindgen_test;
@@ -1126,31 +2021,29 @@ mod tests {
         let sound = Sound {
             buffer: AudioBuffer::new(&options).unwrap(),
         };
-        let rhb = RedHatBoy::new(
-            Sheet {
-                frames: HashMap::new(),
-            },
-            image.clone(),
-            audio,
-            sound,
-        );
-        let sprite_sheet = SpriteSheet::new(
-            Sheet {
-                frames: HashMap::new(),
-            },
-            image.clone(),
-        );
+        let mut sprite_sheet = SpriteSheet::new(Sheet::default(), image.clone());
+        let clips: HashMap<String, ClipDefinition> = serde_json::from_str(
+            r#"{ "Idle": { "frames": [] }, "Run": { "frames": [] }, "Slide": { "frames": [] },
+                 "Jump": { "frames": [] }, "Dead": { "frames": [] } }"#,
+        )
+        .unwrap();
+        sprite_sheet.add_clips(&clips).unwrap();
+        let sprite_sheet = Rc::new(sprite_sheet);
+        let effects = Rc::new(RedHatBoyEffects::new(sprite_sheet.clone()));
+        let rhb = RedHatBoy::new(sprite_sheet.clone(), audio, sound, effects).unwrap();
         let walk = Walk {
+            camera: walk_camera(),
+            hit_stop: HitStop::default(),
             boy: rhb,
-            backgrounds: [
-                Image::new(image.clone(), Point { x: 0, y: 0 }),
-                Image::new(image.clone(), Point { x: 0, y: 0 }),
-            ],
+            background: Parallax::default(),
             obstacles: vec![],
-            obstacle_sheet: Rc::new(sprite_sheet),
+            popups: vec![],
+            obstacle_sheet: sprite_sheet,
             stone: image.clone(),
             score: 0,
             timeline: 0,
+            seed: 0,
+            rng: ChaCha8Rng::seed_from_u64(0),
         };
 
         let document = browser::document().unwrap();
@@ -1163,6 +2056,7 @@ mod tests {
         let state = WalkTheDogState {
             _state: GameOver {
                 new_game_event: receiver,
+                fade: Tween::new(0.0, GAME_OVER_FADE_ALPHA, 0),
             },
             walk: walk,
         };
@@ -1172,4 +2066,128 @@ mod tests {
         let ui = browser::find_html_element_by_id("ui").unwrap();
         assert_eq!(ui.child_element_count(), 0);
     }
+
+    #[test]
+    fn the_same_seed_generates_the_same_segments() {
+        let segments = |seed| {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            (0..50)
+                .map(|_| Segment::random(&mut rng))
+                .collect::<Vec<Segment>>()
+        };
+
+        assert_eq!(segments(1234), segments(1234));
+        assert_ne!(segments(1234), segments(4321));
+    }
+
+    fn cell(x: i16, w: i16, h: i16) -> Cell {
+        Cell {
+            frame: SheetRect { x, y: 0, w, h },
+            rotated: false,
+            trimmed: false,
+            sprite_source_size: SheetRect { x: 0, y: 0, w, h },
+            source_size: SheetSize { w, h },
+            duration: None,
+        }
+    }
+
+    #[test]
+    fn replays_press_actions_with_the_keys_they_were_recorded_with() {
+        let mut bindings = ActionMap::new();
+        bindings.rebind(Action::Jump, &["KeyW"]);
+        let played = SharedControls(Rc::new(RefCell::new(Bindings::new(bindings))));
+
+        let replayed = SharedControls::recorded(Some(&played.to_recording())).unwrap();
+        replayed.load();
+
+        let bindings = replayed.0.borrow().all.clone();
+        assert_eq!(bindings.bindings(Action::Jump), &["KeyW".to_string()]);
+        assert_eq!(
+            bindings.bindings(Action::Slide),
+            default_controls().bindings(Action::Slide)
+        );
+        assert!(replayed.rebind(Action::Jump, &["Space"]).is_err());
+    }
+
+    #[test]
+    fn the_loop_pausing_shows_the_overlay_without_any_updates() {
+        let mut game = WalkTheDog::new();
+        let renderer = RecordingRenderer::new();
+
+        game.on_pause();
+        game.draw_paused(&renderer);
+
+        let text: Vec<Point> = renderer
+            .commands()
+            .into_iter()
+            .filter_map(|command| match command {
+                DrawCommand::DrawStyledText { location, .. } => Some(location),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text.len(), 2);
+        assert!(text
+            .iter()
+            .all(|location| location.y > 20 && location.y < HEIGHT - 20));
+    }
+
+    #[test]
+    fn platform_draws_each_sprite_next_to_the_last() {
+        // An undefined handle stands in for the image; the recording renderer never touches it.
+        let image: HtmlImageElement = JsValue::UNDEFINED.unchecked_into();
+        let mut frames = HashMap::new();
+        frames.insert("13.png".to_string(), cell(0, 60, 54));
+        frames.insert("14.png".to_string(), cell(60, 128, 93));
+        let sheet = Rc::new(SpriteSheet::new(
+            Sheet {
+                frames,
+                ..Sheet::default()
+            },
+            image,
+        ));
+        let platform = Platform::new(sheet, Point { x: 100, y: 400 }, &["13.png", "14.png"], &[]);
+        let renderer = RecordingRenderer::new();
+
+        platform.draw(&renderer);
+
+        assert_eq!(
+            renderer.commands(),
+            vec![
+                DrawCommand::DrawImage {
+                    frame: Rect::new_from_x_y(0, 0, 60, 54),
+                    destination: Rect::new_from_x_y(100, 400, 60, 54),
+                },
+                DrawCommand::DrawImage {
+                    frame: Rect::new_from_x_y(60, 0, 128, 93),
+                    destination: Rect::new_from_x_y(160, 400, 128, 93),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn platform_debug_outlines_every_bounding_box() {
+        let image: HtmlImageElement = JsValue::UNDEFINED.unchecked_into();
+        let sheet = Rc::new(SpriteSheet::new(Sheet::default(), image));
+        let platform = Platform::new(
+            sheet,
+            Point { x: 100, y: 400 },
+            &[],
+            &[
+                Rect::new_from_x_y(0, 0, 60, 54),
+                Rect::new_from_x_y(60, 0, 128, 93),
+            ],
+        );
+        let renderer = RecordingRenderer::new();
+
+        platform.draw_debug(&renderer);
+
+        assert_eq!(
+            renderer.commands(),
+            vec![
+                DrawCommand::DrawRect(Rect::new_from_x_y(100, 400, 60, 54)),
+                DrawCommand::DrawRect(Rect::new_from_x_y(160, 400, 128, 93)),
+            ]
+        );
+    }
 }
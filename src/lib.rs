@@ -5,22 +5,87 @@ mod game;
 mod segments;
 mod sound;
 
-use engine::GameLoop;
-use game::WalkTheDog;
+use engine::{GameLoop, GameLoopHandle};
+use game::{Action, SharedControls, WalkTheDog};
 use wasm_bindgen::prelude::*;
 
 // This is like the `main` function, except for JavaScript.
 #[wasm_bindgen(start)]
 pub fn main_js() -> Result<(), JsValue> {
     console_error_panic_hook::set_once();
+    Ok(())
+}
+
+// A running game, for the page that started it. Keep hold of it to stop the
+// game, e.g. when a single page app moves to another route.
+#[wasm_bindgen]
+pub struct WalkTheDogGame {
+    handle: Option<GameLoopHandle>,
+    controls: SharedControls,
+}
 
-    browser::spawn_local(async move {
-        let game = WalkTheDog::new();
+#[wasm_bindgen]
+impl WalkTheDogGame {
+    // Stops the loop and detaches it from the page. Stopping twice does
+    // nothing.
+    pub fn stop(&mut self) -> Result<(), JsValue> {
+        match self.handle.take() {
+            Some(handle) => handle.stop().map_err(to_js_error),
+            None => Ok(()),
+        }
+    }
 
-        GameLoop::start(game)
-            .await
-            .expect("Could not start game loop");
-    });
+    // Frame timings and the game's counters as of the last frame, e.g.
+    // game.metrics().frameRate
+    pub fn metrics(&self) -> Result<JsValue, JsValue> {
+        let handle = self
+            .handle
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("The game has been stopped"))?;
+        JsValue::from_serde(&handle.metrics()).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
 
-    Ok(())
+    // Replaces the keys bound to an action and saves them for next time,
+    // e.g. game.rebind("Jump", ["KeyW", "GamepadA"])
+    pub fn rebind(&self, action: &str, codes: js_sys::Array) -> Result<(), JsValue> {
+        let action = JsValue::from_str(action)
+            .into_serde::<Action>()
+            .map_err(|err| JsValue::from_str(&format!("Unknown action {}: {:#?}", action, err)))?;
+        let codes: Vec<String> = codes.iter().filter_map(|code| code.as_string()).collect();
+        let codes: Vec<&str> = codes.iter().map(String::as_str).collect();
+        self.controls.rebind(action, &codes).map_err(to_js_error)
+    }
+}
+
+// Starts the game on the page's canvas. Anything that goes wrong loading the
+// game is shown by the loop itself.
+#[wasm_bindgen]
+pub async fn start() -> Result<WalkTheDogGame, JsValue> {
+    // ?replay=run.json plays back a recording saved from localStorage.replay,
+    // starting from the seed and with the controls it was played with
+    let recording = match browser::query_param("replay") {
+        Ok(Some(path)) => Some(engine::load_recording(&path).await.map_err(to_js_error)?),
+        _ => None,
+    };
+    let game = match &recording {
+        Some(recording) => WalkTheDog::replaying(recording).map_err(to_js_error)?,
+        None => WalkTheDog::new(),
+    };
+    let controls = game.controls();
+
+    let started = match recording {
+        Some(recording) => GameLoop::new().replay(game, recording).await,
+        None => GameLoop::new().start(game).await,
+    };
+
+    started
+        .map(|handle| WalkTheDogGame {
+            handle: Some(handle),
+            controls,
+        })
+        .map_err(to_js_error)
+}
+
+fn to_js_error(err: anyhow::Error) -> JsValue {
+    JsValue::from_str(&format!("{:#?}", err))
 }
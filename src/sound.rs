@@ -8,6 +8,12 @@ pub fn create_audio_context() -> Result<AudioContext> {
     AudioContext::new().map_err(|err| anyhow!("Could not create audio context: {:#?}", err))
 }
 
+pub fn close_audio_context(ctx: &AudioContext) -> Result<()> {
+    ctx.close()
+        .map(|_promise| ())
+        .map_err(|err| anyhow!("Could not close audio context: {:#?}", err))
+}
+
 fn create_buffer_source(ctx: &AudioContext) -> Result<AudioBufferSourceNode> {
     ctx.create_buffer_source()
         .map_err(|err| anyhow!("Error creating buffer source {:#?}", err))